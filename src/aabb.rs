@@ -0,0 +1,98 @@
+// Axis-aligned bounding box, a cheap conservative volume around an object.
+// Rays missing the box are guaranteed to miss the object inside it.
+use crate::vec3::Point3;
+use crate::mat4::Mat4;
+use crate::ray::Ray;
+use crate::interval::Interval;
+
+#[derive(Copy, Clone)]
+pub struct Aabb {
+    min: Point3,
+    max: Point3
+}
+
+// flat objects (quads, disks) get at least this thickness
+const MIN_THICKNESS: f64 = 1e-4;
+
+impl Aabb {
+    // box spanned by two opposite corners, in any order
+    pub fn new(a: Point3, b: Point3) -> Aabb {
+        let mut min = Point3::new(a.x().min(b.x()), a.y().min(b.y()), a.z().min(b.z()));
+        let mut max = Point3::new(a.x().max(b.x()), a.y().max(b.y()), a.z().max(b.z()));
+        for i in 0..3 {
+            if max[i] - min[i] < MIN_THICKNESS {
+                min[i] -= MIN_THICKNESS / 2.0;
+                max[i] += MIN_THICKNESS / 2.0;
+            }
+        }
+        Aabb{ min, max }
+    }
+
+    // contains nothing, the identity of union
+    pub fn empty() -> Aabb {
+        Aabb{ min: Point3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY),
+              max: Point3::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY) }
+    }
+    // contains everything, for unbounded objects like planes
+    pub fn everything() -> Aabb {
+        Aabb{ min: Point3::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY),
+              max: Point3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY) }
+    }
+
+    pub fn min(&self) -> Point3 {
+        self.min
+    }
+    pub fn max(&self) -> Point3 {
+        self.max
+    }
+    pub fn center(&self) -> Point3 {
+        (self.min + self.max) / 2.0
+    }
+    pub fn is_finite(&self) -> bool {
+        (0..3).all(|i| self.min[i].is_finite() && self.max[i].is_finite())
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb{ min: Point3::new(self.min.x().min(other.min.x()), self.min.y().min(other.min.y()), self.min.z().min(other.min.z())),
+              max: Point3::new(self.max.x().max(other.max.x()), self.max.y().max(other.max.y()), self.max.z().max(other.max.z())) }
+    }
+    pub fn intersection(&self, other: &Aabb) -> Aabb {
+        Aabb{ min: Point3::new(self.min.x().max(other.min.x()), self.min.y().max(other.min.y()), self.min.z().max(other.min.z())),
+              max: Point3::new(self.max.x().min(other.max.x()), self.max.y().min(other.max.y()), self.max.z().min(other.max.z())) }
+    }
+    // grow the box by margin on every side
+    pub fn pad(&self, margin: f64) -> Aabb {
+        Aabb{ min: self.min - Point3::new(margin, margin, margin),
+              max: self.max + Point3::new(margin, margin, margin) }
+    }
+
+    // box around the transformed eight corners
+    pub fn transform(&self, matrix: &Mat4) -> Aabb {
+        if !self.is_finite() { return *self; }
+        let mut result = Aabb::empty();
+        for i in 0..8 {
+            let corner = Point3::new(if i & 1 == 0 { self.min.x() } else { self.max.x() },
+                                     if i & 2 == 0 { self.min.y() } else { self.max.y() },
+                                     if i & 4 == 0 { self.min.z() } else { self.max.z() });
+            let p = matrix.transform_point(corner);
+            result = result.union(&Aabb{ min: p, max: p });
+        }
+        result
+    }
+
+    // slab test, returns the part of ray_t that lies inside the box
+    pub fn clip(&self, ray: &Ray, ray_t: Interval) -> Option<Interval> {
+        let (mut t_min, mut t_max) = (ray_t.min(), ray_t.max());
+        for i in 0..3 {
+            let inv_d = 1.0 / ray.direction()[i];
+            let mut t0 = (self.min[i] - ray.origin()[i]) * inv_d;
+            let mut t1 = (self.max[i] - ray.origin()[i]) * inv_d;
+            if inv_d < 0.0 { std::mem::swap(&mut t0, &mut t1); }
+            // NaN appears for parallel rays starting on a slab boundary, ignore that slab
+            if t0 > t_min { t_min = t0; }
+            if t1 < t_max { t_max = t1; }
+            if t_max < t_min { return None; }
+        }
+        Some(Interval::new(t_min, t_max))
+    }
+}
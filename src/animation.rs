@@ -0,0 +1,269 @@
+// Keyframe animation: tracks of values at given times, interpolated in between,
+// for the camera and for object transforms, plus rendering a range of frames
+// into numbered image files.
+use crate::vec3::{Vec3, Point3};
+use crate::mat4::Trs;
+use crate::quat::Quat;
+use crate::camera::Camera;
+use crate::hittable::Hittable;
+use crate::transformed::Transformed;
+
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::ops::{Add, Sub, Mul};
+use std::path::Path;
+use std::sync::Arc;
+
+// anything that can be blended linearly can be animated
+pub trait Animatable: Copy + Add<Output = Self> + Sub<Output = Self> + Mul<f64, Output = Self> {}
+impl<T> Animatable for T where T: Copy + Add<Output = T> + Sub<Output = T> + Mul<f64, Output = T> {}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum Interpolation {
+    Linear,
+    CatmullRom,     // smooth curve passing through every key
+    Bezier          // cubic curve shaped by each key's handles
+}
+
+#[derive(Copy, Clone)]
+pub struct Key<T> {
+    pub time: f64,
+    pub value: T,
+    // bezier handles (in, out) as absolute values; when missing,
+    // they're placed so the curve matches catmull-rom
+    pub handles: Option<(T, T)>
+}
+
+#[derive(Clone)]
+pub struct Track<T> {
+    keys: Vec<Key<T>>,  // sorted by time
+    interpolation: Interpolation
+}
+
+impl<T: Animatable> Track<T> {
+    // a track starts out with its first key, so there's always a value to sample
+    pub fn new(interpolation: Interpolation, time: f64, value: T) -> Track<T> {
+        Track{ keys: vec![], interpolation }.key(time, value)
+    }
+    // a value that never changes
+    pub fn constant(value: T) -> Track<T> {
+        Track::new(Interpolation::Linear, 0.0, value)
+    }
+
+    pub fn key(self, time: f64, value: T) -> Track<T> {
+        self.add_key(Key{ time, value, handles: None })
+    }
+    pub fn key_with_handles(self, time: f64, value: T, handle_in: T, handle_out: T) -> Track<T> {
+        self.add_key(Key{ time, value, handles: Some((handle_in, handle_out)) })
+    }
+    // keys can come in any order, they're kept sorted by time
+    fn add_key(mut self, key: Key<T>) -> Track<T> {
+        assert!(key.time.is_finite(), "animation key at time {}!", key.time);
+        let index = self.keys.partition_point(|k| k.time <= key.time);
+        self.keys.insert(index, key);
+        self
+    }
+
+    // before the first and after the last key the value is held
+    pub fn sample(&self, time: f64) -> T {
+        let keys = &self.keys;
+        if time <= keys[0].time { return keys[0].value; }
+        if time >= keys[keys.len() - 1].time { return keys[keys.len() - 1].value; }
+
+        // keys[i] <= time < keys[i + 1]
+        let i = keys.partition_point(|k| k.time <= time) - 1;
+        let (k1, k2) = (&keys[i], &keys[i + 1]);
+        let duration = k2.time - k1.time;
+        let s = (time - k1.time) / duration;
+        // neighbours for the tangents, the end keys repeat themselves one
+        // segment further out
+        let (p1, p2) = (k1.value, k2.value);
+        let (p0, t0) = if i > 0 { (keys[i - 1].value, keys[i - 1].time) } else { (p1, k1.time - duration) };
+        let (p3, t3) = if i + 2 < keys.len() { (keys[i + 2].value, keys[i + 2].time) } else { (p2, k2.time + duration) };
+        // catmull-rom tangents, the slope between the neighbours scaled to
+        // this segment, so unevenly spaced keys don't speed up or overshoot
+        let m1 = (p2 - p0) * (duration / (k2.time - t0));
+        let m2 = (p3 - p1) * (duration / (t3 - k1.time));
+
+        match self.interpolation {
+            Interpolation::Linear => p1 + (p2 - p1) * s,
+            Interpolation::CatmullRom => {
+                // cubic hermite
+                let (s2, s3) = (s * s, s * s * s);
+                p1 * (2.0 * s3 - 3.0 * s2 + 1.0) + m1 * (s3 - 2.0 * s2 + s)
+                    + p2 * (3.0 * s2 - 2.0 * s3) + m2 * (s3 - s2)
+            }
+            Interpolation::Bezier => {
+                let c1 = k1.handles.map_or(p1 + m1 * (1.0 / 3.0), |h| h.1);
+                let c2 = k2.handles.map_or(p2 - m2 * (1.0 / 3.0), |h| h.0);
+                let r = 1.0 - s;
+                p1 * (r * r * r) + c1 * (3.0 * r * r * s) + c2 * (3.0 * r * s * s) + p2 * (s * s * s)
+            }
+        }
+    }
+}
+
+// the animated parameters of a Camera
+#[derive(Clone)]
+pub struct CameraAnimation {
+    pub lookfrom: Track<Point3>,
+    pub lookat: Track<Point3>,
+    pub theta: Track<f64>,
+    pub focus_dist: Track<f64>
+}
+
+impl CameraAnimation {
+    pub fn apply(&self, camera: &mut Camera, time: f64) {
+        camera.set_view(self.lookfrom.sample(time), self.lookat.sample(time),
+                        self.theta.sample(time), self.focus_dist.sample(time));
+    }
+}
+
+// an animated object transform, rotations are keyed separately as quaternions
+// and always slerped between neighbouring keys
+#[derive(Clone)]
+pub struct TransformAnimation {
+    pub translation: Track<Vec3>,
+    rotation: Vec<(f64, Quat)>,     // (time, rotation), sorted by time
+    pub scale: Track<Vec3>
+}
+
+impl TransformAnimation {
+    // the rotation keys can come in any order, without any the object isn't rotated
+    pub fn new(translation: Track<Vec3>, mut rotation: Vec<(f64, Quat)>, scale: Track<Vec3>) -> TransformAnimation {
+        assert!(rotation.iter().all(|&(t, _)| t.is_finite()), "animation key at a time that isn't finite!");
+        rotation.sort_by(|a, b| a.0.total_cmp(&b.0));
+        TransformAnimation{ translation, rotation, scale }
+    }
+
+    pub fn sample(&self, time: f64) -> Trs {
+        let rotation = match self.rotation.iter().position(|&(t, _)| t > time) {
+            None => self.rotation.last().map_or(Quat::identity(), |&(_, q)| q),
+            Some(0) => self.rotation[0].1,
+            Some(i) => {
+                let ((t1, q1), (t2, q2)) = (self.rotation[i - 1], self.rotation[i]);
+                Quat::slerp(q1, q2, (time - t1) / (t2 - t1))
+            }
+        };
+        Trs::new(self.translation.sample(time), rotation, self.scale.sample(time))
+    }
+
+    // place object where it is during one frame, it moves from where it is at
+    // time_open to where it is at time_close, so a camera shutter of (0, 1)
+    // gives the matching motion blur. None if the object gets scaled to
+    // nothing, see Transformed::try_moving
+    pub fn instance(&self, object: Arc<dyn Hittable>, time_open: f64, time_close: f64) -> Option<Transformed> {
+        Transformed::try_moving(object, self.sample(time_open).to_matrix(), self.sample(time_close).to_matrix())
+    }
+}
+
+// turns "frame_####.ppm" into "frame_0042.ppm", the run of '#' gives the
+// number of digits; without any '#' the number is put before the extension
+pub fn frame_path(pattern: &str, frame: u32) -> String {
+    match pattern.find('#') {
+        Some(start) => {
+            let width = pattern[start..].chars().take_while(|&c| c == '#').count();
+            format!("{}{:0width$}{}", &pattern[..start], frame, &pattern[start + width..])
+        }
+        None => match pattern.rfind('.') {
+            Some(dot) => format!("{}{:04}{}", &pattern[..dot], frame, &pattern[dot..]),
+            None => format!("{}{:04}", pattern, frame)
+        }
+    }
+}
+
+// renders frames first..=last into numbered files, frame n showing time n / fps.
+// scene is asked for the camera and world of every frame, given the times the
+// frame's shutter opens and closes. Frames whose file already exists are
+// skipped, so an interrupted sequence can simply be started again. A camera
+// that checkpoints saves frame n into its checkpoint path numbered the same
+// way, which the render removes once the frame is done.
+pub fn render_sequence<F>(first: u32, last: u32, fps: f64, pattern: &str, mut scene: F) -> io::Result<()>
+where F: FnMut(f64, f64) -> (Camera, Arc<dyn Hittable>) {
+    for frame in first..=last {
+        let path = frame_path(pattern, frame);
+        if Path::new(&path).exists() {
+            eprintln!("frame {} already exists, skipped", path);
+            continue;
+        }
+
+        let time = frame as f64 / fps;
+        let (mut camera, world) = scene(time, time + 1.0 / fps);
+        // every frame gets its own checkpoint, numbered like the frames
+        let checkpointing = camera.checkpointing().cloned().map(|mut checkpointing| {
+            checkpointing.path = frame_path(&checkpointing.path, frame);
+            checkpointing
+        });
+        camera.set_checkpointing(checkpointing);
+        // write into a temporary file first, so that a render killed
+        // half way doesn't leave a frame behind that would be skipped
+        let partial = format!("{}.partial", path);
+        camera.render_to(world, &mut BufWriter::new(File::create(&partial)?))?;
+        std::fs::rename(&partial, &path)?;
+        eprintln!("frame {} done", path);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_are_sorted_whatever_order_they_come_in() {
+        let track = Track::new(Interpolation::Linear, 2.0, 20.0).key(0.0, 0.0).key(1.0, 10.0);
+        assert_eq!(track.sample(0.5), 5.0);
+        assert_eq!(track.sample(1.5), 15.0);
+        assert_eq!(track.sample(-1.0), 0.0);
+        assert_eq!(track.sample(3.0), 20.0);
+    }
+
+    #[test]
+    fn catmull_rom_follows_evenly_moving_unevenly_spaced_keys() {
+        // x = t, keyed at uneven times; away from the ends the curve is exact
+        let track = Track::new(Interpolation::CatmullRom, 0.0, 0.0).key(1.0, 1.0).key(3.0, 3.0).key(4.0, 4.0);
+        for k in 0..=10 {
+            let t = 1.0 + 0.2 * k as f64;
+            assert!((track.sample(t) - t).abs() < 1e-12, "x({}) = {}", t, track.sample(t));
+        }
+    }
+
+    #[test]
+    fn catmull_rom_matches_the_uniform_curve_for_even_keys() {
+        let values = [0.0, 2.0, -1.0, 3.0, 5.0];
+        let mut track = Track::new(Interpolation::CatmullRom, 0.0, values[0]);
+        for (k, &value) in values.iter().enumerate().skip(1) {
+            track = track.key(k as f64, value);
+        }
+        for i in 0..values.len() - 1 {
+            let p0 = values[i.saturating_sub(1)];
+            let (p1, p2) = (values[i], values[i + 1]);
+            let p3 = values[(i + 2).min(values.len() - 1)];
+            let s: f64 = 0.3;
+            let (s2, s3) = (s * s, s * s * s);
+            let uniform = (p1 * 2.0 + (p2 - p0) * s + (p0 * 2.0 - p1 * 5.0 + p2 * 4.0 - p3) * s2
+                           + (p1 * 3.0 - p0 - p2 * 3.0 + p3) * s3) * 0.5;
+            assert!((track.sample(i as f64 + s) - uniform).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn rotation_keys_are_sorted() {
+        let quarter = Quat::from_axis_angle(Vec3::new(0.0, 1.0, 0.0), std::f64::consts::FRAC_PI_2);
+        let animation = TransformAnimation::new(Track::constant(Vec3::new(0.0, 0.0, 0.0)),
+                                                vec![(1.0, quarter), (0.0, Quat::identity())],
+                                                Track::constant(Vec3::new(1.0, 1.0, 1.0)));
+        // halfway is an eighth of a turn, +x goes to (cos 45, 0, -sin 45)
+        let x = animation.sample(0.5).to_matrix().transform_vector(Vec3::new(1.0, 0.0, 0.0));
+        let expected = Vec3::new(1.0, 0.0, -1.0).unit_vector();
+        assert!((x - expected).length() < 1e-9, "({}, {}, {})", x.x(), x.y(), x.z());
+        let x = animation.sample(0.0).to_matrix().transform_vector(Vec3::new(1.0, 0.0, 0.0));
+        assert!((x - Vec3::new(1.0, 0.0, 0.0)).length() < 1e-9);
+    }
+
+    #[test]
+    #[should_panic]
+    fn keys_need_a_finite_time() {
+        Track::new(Interpolation::Linear, 0.0, 0.0).key(f64::NAN, 1.0);
+    }
+}
@@ -0,0 +1,232 @@
+// Arbitrary output variables: besides the color, a render can keep what the
+// camera rays hit first (depth, normal, albedo, position, material ID), for
+// compositing and to guide a denoiser.
+//
+// Every pixel averages its own samples, no reconstruction filter: normals
+// and positions shouldn't get blurred across edges. Depth, normal and
+// position average the samples that hit something, albedo all of them
+// (rays into the sky see the sky's color), and the material ID is the one
+// the pixel's first sample hit, IDs don't average.
+use crate::vec3::{Vec3, Point3, Color};
+use crate::sampler::hash;
+use crate::tonemap::srgb_oetf;
+
+use std::io::{self, Read, Write};
+
+const MAX_COLOR: u32 = 255;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Aov {
+    Depth,      // distance from the camera along the ray
+    Normal,     // world space, facing the camera
+    Albedo,
+    Position,   // world space
+    MaterialId  // a color per material
+}
+
+impl Aov {
+    pub const ALL: [Aov; 5] = [Aov::Depth, Aov::Normal, Aov::Albedo, Aov::Position, Aov::MaterialId];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Aov::Depth      => "depth",
+            Aov::Normal     => "normal",
+            Aov::Albedo     => "albedo",
+            Aov::Position   => "position",
+            Aov::MaterialId => "material-id"
+        }
+    }
+    pub fn from_name(name: &str) -> Option<Aov> {
+        Aov::ALL.into_iter().find(|aov| aov.name() == name)
+    }
+}
+
+// what one camera sample hit first. A miss has infinite depth,
+// material ID 0 and the background as albedo
+#[derive(Copy, Clone)]
+pub struct FirstHit {
+    pub depth: f64,
+    pub normal: Vec3,
+    pub albedo: Color,
+    pub position: Point3,
+    pub material_id: u64
+}
+
+impl FirstHit {
+    pub fn miss(background: Color) -> FirstHit {
+        let zero = Vec3::new(0.0, 0.0, 0.0);
+        FirstHit{ depth: f64::INFINITY, normal: zero, albedo: background, position: zero, material_id: 0 }
+    }
+    fn is_hit(&self) -> bool {
+        self.depth.is_finite()
+    }
+}
+
+#[derive(Copy, Clone)]
+struct AovPixel {
+    depth: f64,     // sums
+    normal: Vec3,
+    albedo: Color,
+    position: Point3,
+    hits: u32,
+    samples: u32,
+    material_id: u64
+}
+
+impl Default for AovPixel {
+    fn default() -> AovPixel {
+        let zero = Vec3::new(0.0, 0.0, 0.0);
+        AovPixel{ depth: 0.0, normal: zero, albedo: zero, position: zero, hits: 0, samples: 0, material_id: 0 }
+    }
+}
+
+#[derive(Clone)]
+pub struct AovBuffer {
+    width: u32,
+    height: u32,
+    pixels: Vec<AovPixel>   // row by row from the top
+}
+
+impl AovBuffer {
+    pub fn new(width: u32, height: u32) -> AovBuffer {
+        AovBuffer{ width, height, pixels: vec![AovPixel::default(); width as usize * height as usize] }
+    }
+
+    fn index(&self, row: u32, col: u32) -> usize {
+        row as usize * self.width as usize + col as usize
+    }
+
+    // samples have to be added in the same order every time to get the same image
+    pub fn add(&mut self, row: u32, col: u32, hit: &FirstHit) {
+        let index = self.index(row, col);
+        let pixel = &mut self.pixels[index];
+        if pixel.samples == 0 { pixel.material_id = hit.material_id; }
+        pixel.samples += 1;
+        pixel.albedo += hit.albedo;
+        if !hit.is_hit() { return; }
+        pixel.hits += 1;
+        pixel.depth += hit.depth;
+        pixel.normal += hit.normal;
+        pixel.position += hit.position;
+    }
+
+    // depth comes as (depth, depth, depth), infinite if no sample hit anything
+    pub fn value(&self, row: u32, col: u32, aov: Aov) -> Vec3 {
+        let pixel = &self.pixels[self.index(row, col)];
+        let zero = Vec3::new(0.0, 0.0, 0.0);
+        let per_hit = |sum: Vec3| if pixel.hits == 0 { zero } else { sum / pixel.hits as f64 };
+        match aov {
+            Aov::Depth => {
+                let depth = if pixel.hits == 0 { f64::INFINITY } else { pixel.depth / pixel.hits as f64 };
+                Vec3::new(depth, depth, depth)
+            }
+            Aov::Normal => per_hit(pixel.normal),
+            Aov::Albedo => if pixel.samples == 0 { zero } else { pixel.albedo / pixel.samples as f64 },
+            Aov::Position => per_hit(pixel.position),
+            Aov::MaterialId => id_color(pixel.material_id)
+        }
+    }
+
+    // the values as they are, in the portable float map format
+    // (rows from the bottom, little endian). Misses have depth 0 here
+    pub fn write_pfm(&self, out: &mut impl Write, aov: Aov) -> io::Result<()> {
+        write!(out, "PF\n{} {}\n-1.0\n", self.width, self.height)?;
+        for row in (0..self.height).rev() {
+            for col in 0..self.width {
+                let value = self.value(row, col, aov);
+                for k in 0..3 {
+                    let channel = if value[k].is_finite() { value[k] as f32 } else { 0.0 };
+                    out.write_all(&channel.to_le_bytes())?;
+                }
+            }
+        }
+        out.flush()
+    }
+
+    // a PPM to look at: depth from white (the nearest hit) to black (the farthest),
+    // normals and positions mapped into [0, 1], albedo sRGB encoded
+    pub fn write_ppm(&self, out: &mut impl Write, aov: Aov) -> io::Result<()> {
+        let values: Vec<Vec3> = (0..self.height).flat_map(|row| (0..self.width).map(move |col| (row, col)))
+                                                .map(|(row, col)| self.value(row, col, aov)).collect();
+        let finite = || values.iter().filter(|value| value.x().is_finite());
+        let low = finite().fold(Vec3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY), |low, value| {
+            Vec3::new(low.x().min(value.x()), low.y().min(value.y()), low.z().min(value.z()))
+        });
+        let high = finite().fold(Vec3::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY), |high, value| {
+            Vec3::new(high.x().max(value.x()), high.y().max(value.y()), high.z().max(value.z()))
+        });
+
+        writeln!(out, "P3\n{} {}\n{}", self.width, self.height, MAX_COLOR)?;
+        for row in values.chunks(self.width as usize) {
+            let colors: Vec<String> = row.iter().map(|&value| {
+                let color = match aov {
+                    Aov::Depth if !value.x().is_finite() => Vec3::new(0.0, 0.0, 0.0),
+                    Aov::Depth => {
+                        let near = if high.x() > low.x() { (high.x() - value.x()) / (high.x() - low.x()) } else { 1.0 };
+                        Vec3::new(near, near, near)
+                    }
+                    Aov::Normal => 0.5 * value + Vec3::new(0.5, 0.5, 0.5),
+                    Aov::Albedo => Vec3::new(srgb_oetf(value.x().max(0.0)), srgb_oetf(value.y().max(0.0)),
+                                             srgb_oetf(value.z().max(0.0))),
+                    Aov::Position => {
+                        let mut color = value;
+                        for k in 0..3 {
+                            color[k] = if high[k] > low[k] { (value[k] - low[k]) / (high[k] - low[k]) } else { 0.0 };
+                        }
+                        color
+                    }
+                    Aov::MaterialId => value
+                };
+                let channel = |x: f64| (MAX_COLOR as f64 * x.clamp(0.0, 1.0)).round() as u32;
+                format!("{} {} {}", channel(color.x()), channel(color.y()), channel(color.z()))
+            }).collect();
+            writeln!(out, "{}", colors.join("  "))?;
+        }
+        out.flush()
+    }
+
+    // for checkpoints, see Film::write_raw
+    pub(crate) fn write_raw(&self, out: &mut impl Write) -> io::Result<()> {
+        for pixel in &self.pixels {
+            let vectors = [pixel.normal, pixel.albedo, pixel.position];
+            for value in [pixel.depth].into_iter().chain(vectors.iter().flat_map(|v| [v.x(), v.y(), v.z()])) {
+                out.write_all(&value.to_le_bytes())?;
+            }
+            out.write_all(&pixel.hits.to_le_bytes())?;
+            out.write_all(&pixel.samples.to_le_bytes())?;
+            out.write_all(&pixel.material_id.to_le_bytes())?;
+        }
+        Ok(())
+    }
+    pub(crate) fn read_raw(input: &mut impl Read, width: u32, height: u32) -> io::Result<AovBuffer> {
+        let mut buffer = AovBuffer::new(width, height);
+        let mut bytes = [0_u8; 8];
+        let mut read_f64 = |input: &mut dyn Read| -> io::Result<f64> {
+            input.read_exact(&mut bytes)?;
+            Ok(f64::from_le_bytes(bytes))
+        };
+        for pixel in buffer.pixels.iter_mut() {
+            pixel.depth = read_f64(input)?;
+            for vector in [&mut pixel.normal, &mut pixel.albedo, &mut pixel.position] {
+                for k in 0..3 { vector[k] = read_f64(input)?; }
+            }
+            let mut count = [0_u8; 4];
+            input.read_exact(&mut count)?;
+            pixel.hits = u32::from_le_bytes(count);
+            input.read_exact(&mut count)?;
+            pixel.samples = u32::from_le_bytes(count);
+            let mut id = [0_u8; 8];
+            input.read_exact(&mut id)?;
+            pixel.material_id = u64::from_le_bytes(id);
+        }
+        Ok(buffer)
+    }
+}
+
+// a bright color picked by the ID, black for no material
+fn id_color(id: u64) -> Color {
+    if id == 0 { return Color::new(0.0, 0.0, 0.0); }
+    let bits = hash(&[id]);
+    let channel = |shift: u32| 0.2 + 0.8 * ((bits >> shift) & 0xff) as f64 / 255.0;
+    Color::new(channel(0), channel(8), channel(16))
+}
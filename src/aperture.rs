@@ -0,0 +1,190 @@
+// The shape of the camera's aperture, which is the shape out of focus
+// highlights (bokeh) take. Every aperture maps a uniform random point in
+// [0, 1)^2 to a point on itself, within [-1, 1]^2 so that the thin lens
+// can scale it to the defocus disk.
+use std::f64::consts::PI;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+pub trait Aperture: Send + Sync {
+    fn sample(&self, u: (f64, f64)) -> (f64, f64);
+}
+
+// a round hole, the usual bokeh
+pub struct Circular;
+
+impl Aperture for Circular {
+    fn sample(&self, (u1, u2): (f64, f64)) -> (f64, f64) {
+        let r = u1.sqrt();
+        let phi = 2.0 * PI * u2;
+        (r * phi.cos(), r * phi.sin())
+    }
+}
+
+// straight aperture blades give regular polygons, rotation in radians
+// turns them around the lens axis
+pub struct Polygonal {
+    blades: u32,
+    rotation: f64
+}
+
+impl Polygonal {
+    pub fn new(blades: u32, rotation: f64) -> Polygonal {
+        assert!(blades >= 3, "an aperture needs at least 3 blades!");
+        Polygonal{ blades, rotation }
+    }
+}
+
+impl Aperture for Polygonal {
+    // the polygon is a fan of equal triangles around the center, u1 picks
+    // one of them and is then reused for the position inside it
+    fn sample(&self, (u1, u2): (f64, f64)) -> (f64, f64) {
+        let scaled = u1 * self.blades as f64;
+        let blade = scaled.floor().min(self.blades as f64 - 1.0);
+        let s = scaled - blade;
+        let corner = |k: f64| {
+            let angle = self.rotation + 2.0 * PI * k / self.blades as f64;
+            (angle.cos(), angle.sin())
+        };
+        let (a, b) = (corner(blade), corner(blade + 1.0));
+        // uniform in the triangle (center, a, b)
+        let r = s.sqrt();
+        let (wa, wb) = (r * (1.0 - u2), r * u2);
+        (wa * a.0 + wb * b.0, wa * a.1 + wb * b.1)
+    }
+}
+
+// any shape painted into an image, bright pixels let more light through.
+// The image covers [-1, 1]^2 with its top row at +1
+pub struct ImageAperture {
+    width: usize,
+    height: usize,
+    rows: Vec<f64>,         // cumulative brightness of the rows, ending at 1
+    columns: Vec<Vec<f64>>  // cumulative brightness along each row, ending at 1
+}
+
+impl ImageAperture {
+    // brightness has width * height values, row by row from the top
+    pub fn new(width: usize, height: usize, brightness: Vec<f64>) -> ImageAperture {
+        assert_eq!(Some(brightness.len()), width.checked_mul(height), "aperture image has the wrong number of pixels!");
+        let mut rows = Vec::with_capacity(height);
+        let mut columns = Vec::with_capacity(height);
+        let mut total = 0.0;
+        for row in brightness.chunks(width) {
+            let mut sum = 0.0;
+            let mut cumulative: Vec<f64> = row.iter().map(|&value| { sum += value.max(0.0); sum }).collect();
+            if sum > 0.0 { cumulative.iter_mut().for_each(|value| *value /= sum); }
+            columns.push(cumulative);
+            total += sum;
+            rows.push(total);
+        }
+        assert!(total > 0.0, "aperture image is completely black!");
+        rows.iter_mut().for_each(|value| *value /= total);
+        ImageAperture{ width, height, rows, columns }
+    }
+
+    // reads a PPM, either plain (P3) or binary (P6)
+    pub fn load(path: impl AsRef<Path>) -> io::Result<ImageAperture> {
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, format!("aperture image: {}", message));
+        let data = fs::read(path)?;
+
+        // the header is whitespace separated, with '#' comments
+        let mut position = 0;
+        let mut header = vec![];
+        while header.len() < 4 {
+            while position < data.len() && data[position].is_ascii_whitespace() { position += 1; }
+            if position < data.len() && data[position] == b'#' {
+                while position < data.len() && data[position] != b'\n' { position += 1; }
+                continue;
+            }
+            let start = position;
+            while position < data.len() && !data[position].is_ascii_whitespace() { position += 1; }
+            if start == position { return Err(invalid("header is cut short")); }
+            header.push(String::from_utf8_lossy(&data[start..position]).into_owned());
+        }
+        let number = |text: &str| text.parse::<usize>().map_err(|_| invalid("bad number in header"));
+        let (width, height, max) = (number(&header[1])?, number(&header[2])?, number(&header[3])?);
+        if width == 0 || height == 0 || max == 0 { return Err(invalid("empty image")); }
+        let pixels = width.checked_mul(height).filter(|pixels| pixels.checked_mul(3).is_some())
+                          .ok_or_else(|| invalid("image is too large"))?;
+
+        let values: Vec<f64> = match header[0].as_str() {
+            "P3" => String::from_utf8_lossy(&data[position..]).split_whitespace()
+                        .map(|value| value.parse::<f64>().ok().filter(|value| value.is_finite())
+                                          .ok_or_else(|| invalid("bad pixel value")))
+                        .collect::<io::Result<_>>()?,
+            // one whitespace byte after the header, the file may end right there
+            "P6" if max < 256 => data.get(position + 1..).unwrap_or(&[]).iter().map(|&byte| byte as f64).collect(),
+            _ => return Err(invalid("only P3 and 8 bit P6 images are supported"))
+        };
+        if values.len() < 3 * pixels { return Err(invalid("not enough pixels")); }
+        let brightness: Vec<f64> = values.chunks(3).take(pixels)
+                                         .map(|rgb| (rgb[0] + rgb[1] + rgb[2]) / (3.0 * max as f64)).collect();
+        // no light would get through at all
+        if brightness.iter().all(|&value| value <= 0.0) { return Err(invalid("image is completely black")); }
+        Ok(ImageAperture::new(width, height, brightness))
+    }
+}
+
+impl Aperture for ImageAperture {
+    // pick a row by its share of the brightness, then a pixel in it,
+    // then a uniform point inside that pixel
+    fn sample(&self, (u1, u2): (f64, f64)) -> (f64, f64) {
+        let row = self.rows.partition_point(|&value| value <= u1).min(self.height - 1);
+        let below = if row == 0 { 0.0 } else { self.rows[row - 1] };
+        let v1 = ((u1 - below) / (self.rows[row] - below)).clamp(0.0, 1.0);
+
+        let columns = &self.columns[row];
+        let column = columns.partition_point(|&value| value <= u2).min(self.width - 1);
+        let left = if column == 0 { 0.0 } else { columns[column - 1] };
+        let v2 = ((u2 - left) / (columns[column] - left)).clamp(0.0, 1.0);
+
+        // the leftover fraction of each choice places the point in the pixel
+        let x = (column as f64 + v2) / self.width as f64;
+        let y = (row as f64 + v1) / self.height as f64;
+        (2.0 * x - 1.0, 1.0 - 2.0 * y)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // writes contents into a temporary file and loads it as an aperture
+    fn load(name: &str, contents: &[u8]) -> io::Result<ImageAperture> {
+        let path = std::env::temp_dir().join(format!("raytracer-aperture-{}-{}.ppm", name, std::process::id()));
+        fs::write(&path, contents)?;
+        let aperture = ImageAperture::load(&path);
+        fs::remove_file(&path)?;
+        aperture
+    }
+    fn error(name: &str, contents: &[u8]) -> String {
+        let error = load(name, contents).err().expect("aperture image was accepted");
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        error.to_string()
+    }
+
+    #[test]
+    fn images_load_in_both_formats() {
+        assert!(load("p3", b"P3 2 1 255\n0 0 0 255 255 255\n").is_ok());
+        assert!(load("p6", b"P6 2 1 255\n\x00\x00\x00\xff\xff\xff").is_ok());
+    }
+
+    #[test]
+    fn black_images_are_refused() {
+        assert!(error("black", b"P3 2 2 255\n0 0 0 0 0 0 0 0 0 0 0 0\n").contains("black"));
+    }
+
+    #[test]
+    fn huge_sizes_are_refused() {
+        let header = format!("P6 {} {} 255\n", usize::MAX / 2, 2);
+        assert!(error("huge", header.as_bytes()).contains("too large"));
+    }
+
+    #[test]
+    fn values_that_arent_numbers_are_refused() {
+        assert!(error("nan", b"P3 1 1 255\nnan 0 0\n").contains("bad pixel"));
+        assert!(error("inf", b"P3 1 1 255\ninf 0 0\n").contains("bad pixel"));
+    }
+}
@@ -2,66 +2,390 @@
 // define camera center, focal length, specification of 
 // viewport and image
 // you need to initialize it, render the color then output it to stdout
+// (or any other writer, e.g. a file for each frame of an animation)
 use crate::vec3::{Vec3, Point3, Color};
 use crate::ray::Ray;
-use crate::hittable::Hittable;
+use crate::hittable::{Hittable, HitRecord};
 use crate::interval::Interval;
+use crate::projection::{Projection, Perspective, View, CameraRay};
+use crate::aperture::{Aperture, Circular};
+use crate::sampler::{Sampler, SampleStream, Independent, hash};
+use crate::filter::{Filter, BoxFilter};
+use crate::film::Film;
+use crate::aov::FirstHit;
+use crate::tonemap::DisplayTransform;
+use crate::colorspace::ColorSpace;
+use crate::spectrum::{RgbSpectra, SampledWavelengths, SampledSpectrum};
+use crate::checkpoint;
 
+use std::fs::File;
+use std::path::Path;
+use std::io::{self, BufWriter, Write};
+use std::ops::Range;
 use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::thread;
-use rand::prelude::*;
+use std::time::{Duration, Instant};
 use std::f64::consts::PI;
 
-const MAX_COLOR: u32 = 255;
 const TIMES_REFLECTION: u32 = 50; // maximum reflection times, otherwise may cause stackoverflow
 const NUM_THREADS: u32 = 4;
 const NUM_SAMPLES: u32 = 10;
 const NUM_SAMPLING_THREADS: u32 = 3;
+const ADAPTIVE_BATCH: u32 = 8;  // samples added at a time to pixels that aren't done yet
+const PROBE_GRID: u32 = 16;     // probe rays per side of the image for the fingerprint
+const SKY_HORIZON: Color = Color::new(1.0, 1.0, 1.0);
+const SKY_ZENITH: Color = Color::new(0.5, 0.7, 1.0);
 
-
+// keep sampling a pixel until the standard error of its mean brightness
+// falls below max_error times the brightness, the sampler's
+// samples_per_pixel being the most any pixel gets
 #[derive(Copy, Clone)]
-struct RGB(u32, u32, u32);
+pub struct Adaptive {
+    pub min_samples: u32,
+    pub max_error: f64
+}
+
+// save the render to path every interval, and with resume start from the
+// checkpoint at path if there is one (see checkpoint.rs). scene identifies
+// the world being rendered, e.g. a hash of what it was built from: a
+// checkpoint of another scene isn't resumed
+#[derive(Clone)]
+pub struct Checkpointing {
+    pub path: String,
+    pub interval: Duration,
+    pub resume: bool,
+    pub scene: u64
+}
+
+// a flag shared between the render and whoever may want to stop it,
+// clones all refer to the same flag
+#[derive(Clone, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>
+}
+
+impl CancelToken {
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
 
+#[derive(Clone)]
 pub struct Camera {
     aspect_ratio : f64,
     image_w      : u32,
-    viewport_h   : f64,
+    image_h      : u32,
 
     theta        : f64, // rust has const radian value, like FRAC_PI_4 == pi/4
     
     lookfrom     : Point3,
     lookat       : Point3,
     vup          : Vec3,
-    focal_length : f64,
     defocus_angle : f64,
     focus_dist: f64,
     defocus_disk_u: Vec3,
-    defocus_disk_v: Vec3
+    defocus_disk_v: Vec3,
+    shutter_open : f64,  // rays are shot at random times between these two,
+    shutter_close: f64,  // moving objects get blurred along their motion
+    projection   : Arc<dyn Projection>,
+    aperture     : Arc<dyn Aperture>,
+    sampler      : Arc<dyn Sampler>,
+    filter       : Arc<dyn Filter>,
+    adaptive     : Option<Adaptive>,
+    cancel_token : Option<CancelToken>,
+    time_budget  : Option<Duration>,
+    deadline     : Option<Instant>, // when the time budget of the current render runs out
+    checkpointing: Option<Checkpointing>,
+    aovs         : bool,            // keep the first hits in the film too
+    display      : DisplayTransform,
+    firefly_clamp: Option<f64>,     // brightest a single sample may be
+    working_space: ColorSpace,      // the scene's colors are in it, and so the film
+    spectral     : bool,
+    spectra      : Option<Arc<RgbSpectra>>, // in spectral mode, set up by render
+    fingerprint  : u64,             // of the current render, see fingerprint()
+    view         : View, // camera basis, set up by render
 }
 
 impl Camera {
     pub fn new(lookfrom: Point3, lookat: Point3, theta: f64, defocus_angle: f64, focus_dist: f64) -> Camera {
         Camera{ aspect_ratio: 1.0,
                 image_w     : 400,
-                viewport_h  : 3.0,
+                image_h     : 400,
                 lookfrom, lookat, theta,
                 vup: Vec3::new(0.0, 1.0, 0.0),
-                focal_length: (lookfrom - lookat).length(),
                 defocus_angle, focus_dist,
                 defocus_disk_v:Vec3::new(0.0, 0.0, 0.0), 
                 defocus_disk_u:Vec3::new(0.0, 0.0, 0.0),
+                shutter_open: 0.0, shutter_close: 0.0,
+                projection: Arc::new(Perspective),
+                aperture: Arc::new(Circular),
+                sampler: Arc::new(Independent::new(NUM_SAMPLES, 0)),
+                filter: Arc::new(BoxFilter::new(0.5)),
+                adaptive: None,
+                cancel_token: None, time_budget: None, deadline: None,
+                checkpointing: None, fingerprint: 0, aovs: false,
+                display: DisplayTransform::default(), firefly_clamp: None,
+                working_space: ColorSpace::Srgb, spectral: false, spectra: None,
+                view: View{ origin: lookfrom,
+                            u: Vec3::new(1.0, 0.0, 0.0),
+                            v: Vec3::new(0.0, 1.0, 0.0),
+                            w: Vec3::new(0.0, 0.0, 1.0),
+                            theta, focus_dist, aspect_ratio: 1.0 },
         }
     }
 
+    // objects move between time 0 and 1, e.g. (0.0, 0.5) keeps the shutter
+    // open for the first half of their motion
+    pub fn set_shutter(&mut self, open: f64, close: f64) {
+        self.shutter_open = open;
+        self.shutter_close = close;
+    }
+
+    // move the camera, e.g. to the next frame of an animation
+    pub fn set_view(&mut self, lookfrom: Point3, lookat: Point3, theta: f64, focus_dist: f64) {
+        self.lookfrom = lookfrom;
+        self.lookat = lookat;
+        self.theta = theta;
+        self.focus_dist = focus_dist;
+    }
+
+    // perspective by default, see projection.rs for the others
+    pub fn set_projection(&mut self, projection: Arc<dyn Projection>) {
+        self.projection = projection;
+    }
+
+    // the shape of out of focus highlights, circular by default,
+    // see aperture.rs for the others
+    pub fn set_aperture(&mut self, aperture: Arc<dyn Aperture>) {
+        self.aperture = aperture;
+    }
+
+    // how the samples of a pixel are spread and how many there are,
+    // see sampler.rs
+    pub fn set_sampler(&mut self, sampler: Arc<dyn Sampler>) {
+        self.sampler = sampler;
+    }
+
+    // how samples are spread over the pixels around them, by default
+    // each pixel averages its own samples. See filter.rs
+    pub fn set_filter(&mut self, filter: Arc<dyn Filter>) {
+        self.filter = filter;
+    }
+
+    // None gives every pixel all of the sampler's samples
+    pub fn set_adaptive(&mut self, adaptive: Option<Adaptive>) {
+        self.adaptive = adaptive;
+    }
+
+    // stop rendering when token gets cancelled, e.g. from another thread
+    pub fn set_cancel_token(&mut self, token: Option<CancelToken>) {
+        self.cancel_token = token;
+    }
+
+    // stop rendering once budget went by since the render started
+    pub fn set_time_budget(&mut self, budget: Option<Duration>) {
+        self.time_budget = budget;
+    }
+
+    pub fn set_checkpointing(&mut self, checkpointing: Option<Checkpointing>) {
+        self.checkpointing = checkpointing;
+    }
+    pub fn checkpointing(&self) -> Option<&Checkpointing> {
+        self.checkpointing.as_ref()
+    }
+
+    // have the film keep depth, normal, albedo, position and
+    // material ID of what the camera rays hit first (see aov.rs)
+    pub fn set_aovs(&mut self, aovs: bool) {
+        self.aovs = aovs;
+    }
+
+    // exposure, tone mapping and dithering of the written images
+    pub fn set_display(&mut self, display: DisplayTransform) {
+        self.display = display;
+    }
+
+    // scale samples down to at most this luminance. Rare very bright samples
+    // (fireflies) turn into speckles that take ages to average out; clamping
+    // them loses a little energy but cleans the image up a lot
+    pub fn set_firefly_clamp(&mut self, max_luminance: Option<f64>) {
+        self.firefly_clamp = max_luminance;
+    }
+
+    // the space the scene's colors were converted into, see colorspace.rs
+    pub fn set_working_space(&mut self, working_space: ColorSpace) {
+        self.working_space = working_space;
+    }
+
+    // trace wavelengths instead of RGB (see spectrum.rs)
+    pub fn set_spectral(&mut self, spectral: bool) {
+        self.spectral = spectral;
+    }
+
     pub fn initialize(&mut self, aspect_ratio: f64, image_w: u32) {
         self.aspect_ratio = aspect_ratio;
         self.image_w = image_w;
-        // update viewport_h with theta
-        self.viewport_h = 2.0 * self.focal_length * (self.theta / 2.0).tan();
+        self.image_h = (image_w as f64 / aspect_ratio) as u32;
     }
     
-    pub fn render(&mut self, world: Arc<dyn Hittable + Sync + Send>) {
-        let camera_center = self.lookfrom;
+    pub fn render(&mut self, world: Arc<dyn Hittable>) {
+        self.render_to(world, &mut io::stdout().lock()).expect("fail to write image to stdout!");
+    }
+
+    // render, then write the image as PPM into out
+    pub fn render_to(&mut self, world: Arc<dyn Hittable>, out: &mut impl Write) -> io::Result<()> {
+        self.render_film(world)?.write_ppm(out)
+    }
+
+    // render into a film, which can then be written out in several ways.
+    // If the render is cancelled or runs out of time, the film has
+    // whatever samples each pixel got until then. Only checkpointing
+    // reads and writes files, which is where errors come from; once the
+    // render is complete its checkpoint isn't needed anymore and is removed
+    pub fn render_film(&mut self, world: Arc<dyn Hittable>) -> io::Result<Film> {
+        // passes are only worth it if there's something to do between them
+        let in_passes = self.checkpointing.is_some();
+        let film = self.render_passes(world, in_passes, |_, _| Ok(()))?;
+        if let Some(checkpointing) = &self.checkpointing {
+            // a stopped render keeps it to be resumed
+            if !self.stopped() { std::fs::remove_file(&checkpointing.path)?; }
+        }
+        Ok(film)
+    }
+
+    // render in passes that double the samples per pixel, 1, 2, 4, ... up to
+    // the sampler's samples_per_pixel, overwriting the image at path with what there is
+    // after every pass, so a long render can be looked at early and stopped
+    // when it's good enough. With an interval the image is only written
+    // once that much time went by since the last time, and after the last pass
+    pub fn render_progressive(&mut self, world: Arc<dyn Hittable>, path: &str,
+                              interval: Option<Duration>) -> io::Result<Film> {
+        let mut written = Instant::now();
+        self.render_passes(world, true, |film, last| {
+            if last || interval.is_none_or(|interval| written.elapsed() >= interval) {
+                // write next to it first, so the image is never seen half written
+                let partial = format!("{}.partial", path);
+                film.write_ppm(&mut BufWriter::new(File::create(&partial)?))?;
+                std::fs::rename(&partial, path)?;
+                written = Instant::now();
+            }
+            Ok(())
+        })
+    }
+
+    // the passes doubling the samples per pixel, or without in_passes all
+    // samples in one. after_pass gets the film so far and whether it was the
+    // last pass. Stopping early ends the current pass where it is, so pixels
+    // it didn't reach have fewer samples
+    fn render_passes<F>(&mut self, world: Arc<dyn Hittable>, in_passes: bool, mut after_pass: F) -> io::Result<Film>
+    where F: FnMut(&Film, bool) -> io::Result<()> {
+        self.setup();
+        let mut film = Film::new(self.image_w, self.image_h, self.filter.clone());
+        if self.aovs { film.enable_aovs(); }
+        let mut done = 0;
+        if let Some(checkpointing) = &self.checkpointing {
+            self.fingerprint = self.fingerprint(&world, checkpointing.scene);
+            if checkpointing.resume && Path::new(&checkpointing.path).exists() {
+                (film, done) = checkpoint::load(&checkpointing.path, self.fingerprint, self.filter.clone())?;
+                eprintln!("resuming from {} at {} samples per pixel", checkpointing.path, done);
+            }
+        }
+
+        film.set_display(self.display);
+        film.set_working_space(self.working_space);
+        let film = Arc::new(Mutex::new(film));
+        let last_saved = Arc::new(Mutex::new(Instant::now()));
+        let most = self.sampler.samples_per_pixel();
+        // a resumed render may be in the middle of a pass (done is where it
+        // started), its pixels carry on from the samples they already have
+        let mut next = if in_passes { (done + 1).next_power_of_two() } else { most };
+        while done < most {
+            next = next.clamp(done + 1, most);
+            self.render_pass(world.clone(), &film, done..next, &last_saved);
+            let stopped = self.stopped();
+            if stopped {
+                eprintln!("render stopped during the pass up to {} samples per pixel!", next);
+            } else {
+                eprintln!("{} / {} samples per pixel have been rendered!", next, most);
+                done = next;
+            }
+            let guard = film.lock().unwrap();
+            if let Some(checkpointing) = &self.checkpointing {
+                checkpoint::save(&checkpointing.path, &guard, done, self.fingerprint)?;
+            }
+            after_pass(&guard, stopped || done == most)?;
+            if stopped { break; }
+            next *= 2;
+        }
+        let film = Arc::try_unwrap(film).ok().expect("film still in use!").into_inner().unwrap();
+        Self::report_invalid(&film);
+        Ok(film)
+    }
+
+    fn report_invalid(film: &Film) {
+        let (count, pixels) = film.invalid_samples();
+        if count == 0 { return; }
+        let pixels: Vec<String> = pixels.iter().map(|(row, col)| format!("({}, {})", row, col)).collect();
+        eprintln!("{} samples were NaN or infinite and counted as black, at pixels (row, col) {}{}",
+                  count, pixels.join(" "), if count > pixels.len() as u64 { " ..." } else { "" });
+    }
+
+    fn stopped(&self) -> bool {
+        self.cancel_token.as_ref().is_some_and(|token| token.is_cancelled())
+            || self.deadline.is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    // identifies the scene and the render settings: the scene by the id it
+    // was given, the settings by their values, and the sampler and filter
+    // are asked for a few values. Probe rays through a grid over the image
+    // hit the world and scatter off its materials on top, to catch a scene
+    // changed without its id
+    fn fingerprint(&self, world: &Arc<dyn Hittable>, scene: u64) -> u64 {
+        let bits = |v: Vec3| [v.x().to_bits(), v.y().to_bits(), v.z().to_bits()];
+        let mut values = vec![scene, TIMES_REFLECTION as u64,
+                              self.image_w as u64, self.image_h as u64, self.sampler.samples_per_pixel() as u64,
+                              self.filter.radius().to_bits(), self.filter.evaluate(0.3, 0.2).to_bits(),
+                              self.shutter_open.to_bits(), self.shutter_close.to_bits(), self.aovs as u64,
+                              self.firefly_clamp.map_or(0, f64::to_bits), self.working_space as u64,
+                              self.spectral as u64];
+        values.extend(bits(SKY_HORIZON).into_iter().chain(bits(SKY_ZENITH)));
+        if let Some(adaptive) = self.adaptive {
+            values.extend([adaptive.min_samples as u64, adaptive.max_error.to_bits()]);
+        }
+        let time = 0.5 * (self.shutter_open + self.shutter_close);
+        for probe in 0..PROBE_GRID * PROBE_GRID {
+            let x = ((probe % PROBE_GRID) as f64 + 0.5) / PROBE_GRID as f64;
+            let y = ((probe / PROBE_GRID) as f64 + 0.5) / PROBE_GRID as f64;
+            let mut sampler = SampleStream::new(self.sampler.as_ref(), (probe, 0), 0);
+            let lens = sampler.get_2d();
+            values.extend([lens.0.to_bits(), lens.1.to_bits()]);
+            let Some(camera_ray) = self.projection.generate_ray(&self.view, x, y, lens) else { continue; };
+            let camera_ray = self.defocus(camera_ray, lens);
+            let ray = Ray::with_time(camera_ray.origin, camera_ray.direction, time);
+            values.extend(bits(ray.origin()).into_iter().chain(bits(ray.direction())));
+            values.push(camera_ray.weight.to_bits());
+            let Some(record) = world.hit(&ray, Interval::new(0.001, f64::INFINITY)) else { continue; };
+            values.push(record.t.to_bits());
+            values.extend(bits(record.normal));
+            if let Some((attenuation, scattered)) = record.material.scatter(&ray, &record, &mut sampler) {
+                values.extend(bits(attenuation).into_iter().chain(bits(scattered.direction())));
+            }
+        }
+        hash(&values)
+    }
+
+    // camera basis and defocus disk for this render
+    fn setup(&mut self) {
+        self.deadline = self.time_budget.map(|budget| Instant::now() + budget);
+        self.spectra = self.spectral.then(|| Arc::new(RgbSpectra::new(self.working_space)));
+
         let w = (self.lookfrom - self.lookat).unit_vector();
         let u = ((-w).cross(&self.vup.unit_vector())).unit_vector();
         let v = u.cross(&-w).unit_vector();
@@ -70,50 +394,49 @@ impl Camera {
         self.defocus_disk_u = u * defocus_radius;
         self.defocus_disk_v = v * defocus_radius;
 
-        self.viewport_h = 2.0 * self.focus_dist * (self.theta/2.0).tan();
-       
         // related variables initialization
-        let image_h = (self.image_w as f64 / self.aspect_ratio) as u32;
-        println!("P3\n{} {}\n{}", self.image_w, image_h, MAX_COLOR);
-
-        let viewport_w = self.viewport_h * (self.image_w as f64 / image_h as f64);
-        // viewport_u is direction vector, its length is viewport_w, its direction 
-        // specifies the direction of longer edge(width)
-        // viewport_v is same as viewport_u, but specifies the direction of height
-        let viewport_u = viewport_w * u; 
-        let viewport_v = self.viewport_h * v;
-
-        // define the upper left position of viewport
-        // camera_center - (focal_length * w) approximates position of lookat
-        let vp_upper_left_pos: Point3 = 
-        camera_center - (self.focus_dist * w) - viewport_u/2.0 + viewport_v/2.0;
-
-        // define the gap between two pixels, then define the position of upper left pixel
-        let delta_u = viewport_u / self.image_w as f64;
-        let delta_v = viewport_v / image_h as f64;
-        let pixel00_loc: Point3 = vp_upper_left_pos + 0.5 * (delta_u - delta_v);
-
-        // rendering, multithreading version
-        let width = self.image_w;
-        use crate::{IMAGE_WIDTH, IMAGE_HEIGHT};
-        let canvas = Arc::new(Mutex::new(vec![vec![RGB(0, 0, 0); IMAGE_WIDTH as usize]; IMAGE_HEIGHT as usize]));
+        self.view = View{ origin: self.lookfrom, u, v, w,
+                          theta: self.theta, focus_dist: self.focus_dist,
+                          aspect_ratio: self.image_w as f64 / self.image_h as f64 };
+    }
+
+    // shoot the samples with the given indices of every pixel into film
+    // with checkpointing the film is also saved now and then between two columns
+    fn render_pass(&self, world: Arc<dyn Hittable>, film: &Arc<Mutex<Film>>, indices: Range<u32>,
+                   last_saved: &Arc<Mutex<Instant>>) {
+        // rendering, multithreading version, every thread gets its own copy of the camera
+        let (width, image_h) = (self.image_w, self.image_h);
+        let camera = Arc::new(self.clone());
         let total = Arc::new(Mutex::new(0_u32));
         let mut threads = vec![];
         for i in 0..NUM_THREADS {
-            let canvas_ref = canvas.clone();
+            let film_ref = film.clone();
             let world_ref = world.clone();
             let total_ref = total.clone();
+            let camera_ref = camera.clone();
+            let indices = indices.clone();
+            let last_saved_ref = last_saved.clone();
             threads.push(thread::spawn(move || {
                 let mut col = i;
                 while col < width {
+                    if camera_ref.stopped() { break; }
                     for j in 0..image_h {
-                        let temp = Self::write_color(Self::get_sample_ray(j, col, pixel00_loc, delta_u, delta_v, world_ref.clone()));
-                        let mut guard = canvas_ref.lock().unwrap();
-                        (*guard)[j as usize][col as usize] = temp;
+                        camera_ref.render_pixel(j, col, world_ref.clone(), &film_ref, indices.clone());
                     }
                     let mut guard = total_ref.lock().unwrap();
                     *guard += 1;
                     eprintln!("{} / {} columns have been rendered!", *guard, width);
+                    drop(guard);
+                    if let Some(checkpointing) = &camera_ref.checkpointing {
+                        let mut saved = last_saved_ref.lock().unwrap();
+                        if saved.elapsed() >= checkpointing.interval {
+                            let film = film_ref.lock().unwrap();
+                            if let Err(e) = checkpoint::save(&checkpointing.path, &film, indices.start, camera_ref.fingerprint) {
+                                eprintln!("fail to save checkpoint: {}", e);
+                            }
+                            *saved = Instant::now();
+                        }
+                    }
                     col += NUM_THREADS;
                 }
             }));
@@ -121,43 +444,103 @@ impl Camera {
         for thread in threads {
             thread.join().expect("thread spawn fail!");
         }
-         
-        // write canvas into file
-        let guard = canvas.lock().unwrap();
-        for i in 0..image_h as usize {
-            for j in 0..self.image_w as usize {
-                print!("{} {} {}", (*guard)[i][j].0, (*guard)[i][j].1, (*guard)[i][j].2); 
-                if j == self.image_w as usize - 1 {
-                    print!("\n");
-                } else {
-                    print!("  ");
+    }
+
+    // the samples of one pixel, or with adaptive sampling batches of
+    // them until the pixel is smooth enough
+    fn render_pixel(&self, i: u32, j: u32, world: Arc<dyn Hittable>, film: &Mutex<Film>, indices: Range<u32>) {
+        // after resuming some samples may be there already
+        let mut taken = indices.start.max(film.lock().unwrap().samples(i, j));
+        while taken < indices.end && !self.stopped() {
+            let count = match self.adaptive {
+                None => indices.end - taken,
+                Some(adaptive) => {
+                    let (samples_so_far, error) = {
+                        let guard = film.lock().unwrap();
+                        (guard.samples(i, j), guard.relative_error(i, j))
+                    };
+                    // the error is only looked at after the first batch and every
+                    // ADAPTIVE_BATCH samples from there, wherever the passes cut
+                    // the batches, so the image doesn't depend on the passes
+                    let first = adaptive.min_samples.max(2);
+                    if samples_so_far < first {
+                        first - samples_so_far
+                    } else {
+                        let into_batch = (samples_so_far - first) % ADAPTIVE_BATCH;
+                        if into_batch == 0 && samples_so_far >= adaptive.min_samples
+                            && error <= adaptive.max_error { break; }
+                        ADAPTIVE_BATCH - into_batch
+                    }
+                }
+            }.min(indices.end - taken);
+            let samples = self.sample_pixel(i, j, world.clone(), taken..taken + count);
+            taken += count;
+
+            let mut guard = film.lock().unwrap();
+            for (x, y, color, first_hit) in samples {
+                let mut color = guard.check_sample(i, j, color);
+                if let Some(max_luminance) = self.firefly_clamp {
+                    let luminance = self.working_space.luminance(color);
+                    if luminance > max_luminance { color *= max_luminance / luminance; }
+                }
+                guard.add_sample(x, y, color);
+                guard.record(i, j, color);
+                if let Some(first_hit) = first_hit {
+                    guard.record_first_hit(i, j, &first_hit);
                 }
             }
         }
     }
 
-    // determine NUM_SAMPLES random pixels in current square, get their rays
-    fn get_sample_ray(i: u32, j: u32, pixel00_loc: Point3, delta_u: Vec3, delta_v: Vec3, world: Arc<dyn Hittable + Sync + Send>) -> Color {
-        let center_pixel: Point3 = pixel00_loc + j as f64 * delta_u - i as f64 * delta_v;
-        let pixel_color = Arc::new(Mutex::new(Color::new(0.0, 0.0, 0.0)));
-        let origin = Point3::new(13.0, 2.0, 3.0);
-        // let origin = if self.defocus_angle <= 0.0 {self.lookfrom} else {self.defocus_disk_sample()};
+    // shoot the sampler's samples of this pixel, each at its own spot of the
+    // pixel, time while the shutter is open and point of the lens. Gives
+    // every sample's position on the image in pixels and its color, and
+    // what it hit first if the film keeps AOVs.
+    // Sampling threads take every NUM_SAMPLING_THREADS-th sample of indices
+    fn sample_pixel(&self, i: u32, j: u32, world: Arc<dyn Hittable>, indices: Range<u32>)
+                    -> Vec<(f64, f64, Color, Option<FirstHit>)> {
+        let (image_w, image_h) = (self.image_w as f64, self.image_h as f64);
+        let (shutter_open, shutter_close) = (self.shutter_open, self.shutter_close);
+        let first = indices.start;
+        let sample_colors = Arc::new(Mutex::new(vec![(0.0, 0.0, Color::new(0.0, 0.0, 0.0), None); indices.len()]));
         let mut threads = vec![];
-        for _ in 0..NUM_SAMPLING_THREADS {
-            let pixel_color_ref = pixel_color.clone();
+        for thread_index in 0..NUM_SAMPLING_THREADS {
+            let sample_colors_ref = sample_colors.clone();
             let world_ref = world.clone();
+            let camera = self.clone();
+            let end = indices.end;
             threads.push(thread::spawn(move || {
-                let mut times = NUM_SAMPLES / NUM_SAMPLING_THREADS;
-                if i == NUM_SAMPLING_THREADS - 1 { times += NUM_SAMPLES % NUM_SAMPLING_THREADS; }
-                for _ in 0..times {
-                    let random = thread_rng().gen_range(-0.5..=0.5);
-                    let random_pixel = center_pixel + random * delta_u + random * delta_v;
-                    // code below will cause lifetime issue, so I put them outside closure
-                    // let origin = if self.defocus_angle <= 0.0 {self.lookfrom} else {self.defocus_disk_sample()};    
-                    let ray = Ray::new(origin, random_pixel - origin);
-                    let color = Self::ray_color(&ray, world_ref.clone(), TIMES_REFLECTION);
-                    let mut guard = pixel_color_ref.lock().unwrap();
-                    *guard += color;
+                for index in (first + thread_index..end).step_by(NUM_SAMPLING_THREADS as usize) {
+                    let mut sampler = SampleStream::new(camera.sampler.as_ref(), (j, i), index);
+                    let (dx, dy) = sampler.get_2d();
+                    let x = (j as f64 + dx) / image_w;
+                    let y = (i as f64 + dy) / image_h;
+                    let time = shutter_open + sampler.get_1d() * (shutter_close - shutter_open);
+                    let lens = sampler.get_2d();
+                    // directions the projection doesn't cover stay black
+                    let (color, first_hit) = match camera.projection.generate_ray(&camera.view, x, y, lens) {
+                        None => (Color::new(0.0, 0.0, 0.0), camera.aovs.then(|| FirstHit::miss(Color::new(0.0, 0.0, 0.0)))),
+                        Some(camera_ray) => {
+                            let camera_ray = camera.defocus(camera_ray, lens);
+                            let ray = Ray::with_time(camera_ray.origin, camera_ray.direction, time);
+                            // the AOVs are what this hit is, the color where it goes from there
+                            let hit = world_ref.hit(&ray, Interval::new(0.001, f64::INFINITY));
+                            let first_hit = camera.aovs.then(|| camera.first_hit(&ray, hit.as_ref()));
+                            let color = match &camera.spectra {
+                                None => camera.shade(&ray, hit, world_ref.clone(), TIMES_REFLECTION, &mut sampler),
+                                Some(spectra) => {
+                                    let mut wavelengths = SampledWavelengths::sample(sampler.get_1d());
+                                    let ray = ray.with_wavelength(Some(wavelengths.hero()));
+                                    let radiance = camera.shade_spectrum(&ray, hit, world_ref.clone(), TIMES_REFLECTION,
+                                                                         &mut sampler, &mut wavelengths);
+                                    spectra.to_rgb(radiance, &wavelengths)
+                                }
+                            };
+                            (camera_ray.weight * color, first_hit)
+                        }
+                    };
+                    let mut guard = sample_colors_ref.lock().unwrap();
+                    (*guard)[(index - first) as usize] = (j as f64 + dx, i as f64 + dy, color, first_hit);
                 }
             }));
         }
@@ -165,44 +548,141 @@ impl Camera {
             thread.join().expect("thread spawn error!");
         }
 
-        // concerning lifetime of temporaries in Rust
-        let x = *pixel_color.lock().unwrap(); x
+        let x = sample_colors.lock().unwrap().clone(); x
     }
 
     // Given a ray at some position in world, what is its color?
-    fn ray_color(ray: &Ray, world: Arc<dyn Hittable + Sync + Send>, depth: u32) -> Color {
-        let unit_direction = ray.direction().unit_vector();
-        let a = 0.5*(unit_direction.y() + 1.0);
-        let default_color = (1.0-a)*Color::new(1.0, 1.0, 1.0) + a*Color::new(0.5, 0.7, 1.0);
-
-        if depth <= 0 { return default_color; }
+    fn ray_color(&self, ray: &Ray, world: Arc<dyn Hittable>, depth: u32, sampler: &mut SampleStream) -> Color {
+        if depth == 0 { return self.background(ray); }
+        let hit = world.hit(ray, Interval::new(0.001, f64::INFINITY));
+        self.shade(ray, hit, world, depth, sampler)
+    }
 
-        match world.hit(ray, Interval::new(0.001, std::f64::INFINITY)) {
-            None => default_color,
+    // the color of a ray that hit what it hit
+    fn shade(&self, ray: &Ray, hit: Option<HitRecord>, world: Arc<dyn Hittable>, depth: u32,
+             sampler: &mut SampleStream) -> Color {
+        match hit {
+            None => self.background(ray),
             Some(record) => {
-                match record.material.scatter(ray, &record) {
+                match record.material.scatter(ray, &record, sampler) {
                     None => Color::new(0.0, 0.0, 0.0),
                     Some((attenuation, scattered_ray)) => {
-                        attenuation * Self::ray_color(&scattered_ray, world.clone(), depth - 1)
+                        attenuation * self.ray_color(&scattered_ray, world.clone(), depth - 1, sampler)
                     }
                 }
             }
         }
     }
 
-    // Write color(RGB) to stdout.
-    fn write_color(mut pixel_color: Color) -> RGB {
-        pixel_color /= NUM_SAMPLES as f64;
-        pixel_color.sqrt(); // linear to gamma transform
+    // ray_color with a spectrum at the sampled wavelengths
+    fn ray_spectrum(&self, ray: &Ray, world: Arc<dyn Hittable>, depth: u32, sampler: &mut SampleStream,
+                    wavelengths: &mut SampledWavelengths) -> SampledSpectrum {
+        if depth == 0 { return self.background_spectrum(ray, wavelengths); }
+        let hit = world.hit(ray, Interval::new(0.001, f64::INFINITY));
+        self.shade_spectrum(ray, hit, world, depth, sampler, wavelengths)
+    }
+
+    fn shade_spectrum(&self, ray: &Ray, hit: Option<HitRecord>, world: Arc<dyn Hittable>, depth: u32,
+                      sampler: &mut SampleStream, wavelengths: &mut SampledWavelengths) -> SampledSpectrum {
+        match hit {
+            None => self.background_spectrum(ray, wavelengths),
+            Some(record) => {
+                match record.material.scatter_spectral(ray, &record, sampler, wavelengths, self.spectra()) {
+                    None => SampledSpectrum::constant(0.0),
+                    Some((attenuation, scattered_ray)) => {
+                        attenuation * self.ray_spectrum(&scattered_ray, world.clone(), depth - 1, sampler, wavelengths)
+                    }
+                }
+            }
+        }
+    }
 
-        RGB((MAX_COLOR as f64 * pixel_color.x()) as u32,
-            (MAX_COLOR as f64 * pixel_color.y()) as u32,
-            (MAX_COLOR as f64 * pixel_color.z()) as u32)
+    // the sky, white at the horizon to blue above (in sRGB)
+    fn background(&self, ray: &Ray) -> Color {
+        let a = Self::sky_blend(ray);
+        let sky = (1.0-a)*SKY_HORIZON + a*SKY_ZENITH;
+        ColorSpace::Srgb.convert(sky, self.working_space)
+    }
+    // mixing the two spectra mixes the colors the same way, and
+    // keeps the spectra cached for the two colors only
+    fn background_spectrum(&self, ray: &Ray, wavelengths: &SampledWavelengths) -> SampledSpectrum {
+        let a = Self::sky_blend(ray);
+        let spectra = self.spectra();
+        let horizon = spectra.illuminant(ColorSpace::Srgb.convert(SKY_HORIZON, self.working_space), wavelengths);
+        let zenith = spectra.illuminant(ColorSpace::Srgb.convert(SKY_ZENITH, self.working_space), wavelengths);
+        horizon * (1.0-a) + zenith * a
+    }
+    fn spectra(&self) -> &RgbSpectra {
+        self.spectra.as_deref().expect("spectra are only set up for spectral renders!")
+    }
+    fn sky_blend(ray: &Ray) -> f64 {
+        let unit_direction = ray.direction().unit_vector();
+        0.5*(unit_direction.y() + 1.0)
     }
 
-    fn defocus_disk_sample(&self) -> Point3 {
-        let p = Vec3::random_in_unit_disk();
+    // what the camera ray sees first, for the AOVs
+    fn first_hit(&self, ray: &Ray, hit: Option<&HitRecord>) -> FirstHit {
+        match hit {
+            None => FirstHit::miss(self.background(ray)),
+            Some(record) => FirstHit{ depth: record.t * ray.direction().length(), normal: record.normal,
+                                      albedo: record.material.albedo(), position: record.p,
+                                      material_id: record.material.id() }
+        }
+    }
+
+    // thin lens: move the ray's origin to a point of the aperture around it and
+    // aim at the point it saw on the plane focus_dist in front of the camera,
+    // so only that plane stays sharp
+    fn defocus(&self, ray: CameraRay, lens: (f64, f64)) -> CameraRay {
+        if self.defocus_angle <= 0.0 || self.projection.has_lens() { return ray; }
+        let forward = -ray.direction.dot(&self.view.w);
+        if forward <= 0.0 { return ray; }   // looking sideways or back, e.g. in panoramas
+        let focus_point = ray.origin + (self.focus_dist / forward) * ray.direction;
+        let origin = self.defocus_disk_sample(ray.origin, lens);
+        CameraRay{ origin, direction: focus_point - origin, weight: ray.weight }
+    }
+
+    fn defocus_disk_sample(&self, center: Point3, lens: (f64, f64)) -> Point3 {
+        let (x, y) = self.aperture.sample(lens);
         
-        self.lookfrom + (p.x() * self.defocus_disk_u) + (p.y() * self.defocus_disk_v)
+        center + (x * self.defocus_disk_u) + (y * self.defocus_disk_v)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sphere::Sphere;
+    use crate::hittable_list::HittableList;
+    use crate::material::{Lambertian, Metal};
+    use crate::sampler::{Stratified, Sobol};
+
+    fn render(sampler: Arc<dyn Sampler>) -> Vec<Color> {
+        let mut world = HittableList::new();
+        world.add(Arc::new(Sphere::new(Point3::new(0.0, -100.5, -1.0), 100.0,
+                                       Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))))));
+        world.add(Arc::new(Sphere::new(Point3::new(0.0, 0.0, -1.0), 0.5,
+                                       Arc::new(Metal::new(Color::new(0.8, 0.6, 0.2), 0.3)))));
+        let mut camera = Camera::new(Point3::new(0.0, 0.0, 1.0), Point3::new(0.0, 0.0, -1.0), PI / 4.0, 2.0, 2.0);
+        camera.set_sampler(sampler);
+        camera.initialize(1.0, 8);
+        let film = camera.render_film(Arc::new(world)).unwrap();
+        (0..film.height()).flat_map(|row| (0..film.width()).map(move |col| (row, col)))
+                          .map(|(row, col)| film.pixel(row, col)).collect()
+    }
+    fn bits(colors: &[Color]) -> Vec<[u64; 3]> {
+        colors.iter().map(|color| [color.x().to_bits(), color.y().to_bits(), color.z().to_bits()]).collect()
+    }
+
+    #[test]
+    fn same_seed_renders_the_same_image() {
+        let samplers: [fn(u64) -> Arc<dyn Sampler>; 3] = [|seed| Arc::new(Independent::new(8, seed)),
+                                                          |seed| Arc::new(Stratified::new(3, 3, seed)),
+                                                          |seed| Arc::new(Sobol::new(8, seed))];
+        for sampler in samplers {
+            let image = bits(&render(sampler(1)));
+            assert_eq!(image, bits(&render(sampler(1))));
+            assert_ne!(image, bits(&render(sampler(2))));
+        }
     }
 }
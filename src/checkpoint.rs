@@ -0,0 +1,54 @@
+// Checkpoints let a long render carry on after the machine went down: the
+// film's sums and per-pixel sample counts are saved now and then, together
+// with how many samples per pixel were finished and a fingerprint of the scene
+// and settings (see Camera::fingerprint). A checkpoint only resumes the render
+// it came from, samplers are stateless so the samples still to come are
+// exactly the ones the interrupted render would have taken.
+use crate::film::Film;
+use crate::filter::Filter;
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::sync::Arc;
+
+const MAGIC: &[u8; 8] = b"RTCKPT04";
+
+// done is the samples per pixel of the last finished pass
+pub fn save(path: &str, film: &Film, done: u32, fingerprint: u64) -> io::Result<()> {
+    // written next to it first, a crash while saving keeps the old checkpoint
+    let partial = format!("{}.partial", path);
+    let mut out = BufWriter::new(File::create(&partial)?);
+    out.write_all(MAGIC)?;
+    out.write_all(&fingerprint.to_le_bytes())?;
+    for value in [film.width(), film.height(), done] {
+        out.write_all(&value.to_le_bytes())?;
+    }
+    film.write_raw(&mut out)?;
+    out.flush()?;
+    drop(out);
+    std::fs::rename(&partial, path)
+}
+
+// gives the film and the samples per pixel finished, fails if the
+// checkpoint is from another scene or other settings
+pub fn load(path: &str, fingerprint: u64, filter: Arc<dyn Filter>) -> io::Result<(Film, u32)> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, format!("checkpoint {}: {}", path, message));
+    let mut input = BufReader::new(File::open(path)?);
+    let mut magic = [0_u8; 8];
+    input.read_exact(&mut magic)?;
+    if &magic != MAGIC { return Err(invalid("not a checkpoint file")); }
+
+    let mut bytes = [0_u8; 8];
+    input.read_exact(&mut bytes)?;
+    if u64::from_le_bytes(bytes) != fingerprint {
+        return Err(invalid("the scene or the render settings changed since it was saved"));
+    }
+    let mut header = [0_u32; 3];
+    for value in header.iter_mut() {
+        input.read_exact(&mut bytes[..4])?;
+        *value = u32::from_le_bytes(bytes[..4].try_into().unwrap());
+    }
+    let [width, height, done] = header;
+    let film = Film::read_raw(&mut input, width, height, filter)?;
+    Ok((film, done))
+}
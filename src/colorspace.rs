@@ -0,0 +1,133 @@
+// Color spaces: a Color is three numbers, what they mean depends on the
+// primaries they're measured against. The renderer works in one linear
+// working space, every color is in it: colors authored in another space
+// (TaggedColor, e.g. ColorSpace::Srgb.color(r, g, b)) are converted when the
+// scene is built, and the film is converted to the output space when
+// written, which the image gets tagged with.
+//
+// Conversions go through CIE XYZ with the D65 white point. ACEScg is
+// defined with a D60 white, it's adapted with the Bradford transform.
+use crate::vec3::Color;
+
+pub(crate) type Mat3 = [[f64; 3]; 3];
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ColorSpace {
+    Srgb,       // linear, rec.709 primaries, what colors in this repo were written in
+    AcesCg,     // ACES AP1 primaries, a wider gamut that's nice to light in
+    DisplayP3   // linear, the primaries of wide gamut screens
+}
+
+impl ColorSpace {
+    pub fn from_name(name: &str) -> Option<ColorSpace> {
+        match name {
+            "srgb" | "rec709" => Some(ColorSpace::Srgb),
+            "acescg"          => Some(ColorSpace::AcesCg),
+            "display-p3"      => Some(ColorSpace::DisplayP3),
+            _ => None
+        }
+    }
+    pub fn name(self) -> &'static str {
+        match self {
+            ColorSpace::Srgb      => "srgb",
+            ColorSpace::AcesCg    => "acescg",
+            ColorSpace::DisplayP3 => "display-p3"
+        }
+    }
+
+    pub fn to_xyz(self) -> Mat3 {
+        match self {
+            ColorSpace::Srgb      => SRGB_TO_XYZ,
+            ColorSpace::AcesCg    => mul_matrix(&D60_TO_D65, &AP1_TO_XYZ),
+            ColorSpace::DisplayP3 => P3_TO_XYZ
+        }
+    }
+    pub fn from_xyz(self) -> Mat3 {
+        match self {
+            ColorSpace::Srgb      => XYZ_TO_SRGB,
+            ColorSpace::AcesCg    => mul_matrix(&XYZ_TO_AP1, &D65_TO_D60),
+            ColorSpace::DisplayP3 => XYZ_TO_P3
+        }
+    }
+
+    // perceived brightness (CIE Y) of a linear color in this space
+    pub fn luminance(self, color: Color) -> f64 {
+        let [r, g, b] = self.to_xyz()[1];
+        r * color.x() + g * color.y() + b * color.z()
+    }
+
+    // a color given in this space, to be brought into the working space, e.g.
+    // ColorSpace::Srgb.color(0.8, 0.2, 0.1).in_space(working_space)
+    pub fn color(self, r: f64, g: f64, b: f64) -> TaggedColor {
+        self.tag(Color::new(r, g, b))
+    }
+    pub fn tag(self, color: Color) -> TaggedColor {
+        TaggedColor{ color, space: self }
+    }
+
+    // takes color from this space into target
+    pub fn conversion(self, target: ColorSpace) -> Mat3 {
+        mul_matrix(&target.from_xyz(), &self.to_xyz())
+    }
+    pub fn convert(self, color: Color, target: ColorSpace) -> Color {
+        if self == target { return color; }
+        mul(&self.conversion(target), color)
+    }
+}
+
+// a color and the space it's in
+#[derive(Copy, Clone)]
+pub struct TaggedColor {
+    pub color: Color,
+    pub space: ColorSpace
+}
+
+impl TaggedColor {
+    pub fn in_space(self, target: ColorSpace) -> Color {
+        self.space.convert(self.color, target)
+    }
+}
+
+// rows of a 3x3 matrix times a column vector
+pub(crate) fn mul(matrix: &Mat3, v: Color) -> Color {
+    let row = |r: &[f64; 3]| r[0] * v.x() + r[1] * v.y() + r[2] * v.z();
+    Color::new(row(&matrix[0]), row(&matrix[1]), row(&matrix[2]))
+}
+
+// a applied after b
+pub(crate) fn mul_matrix(a: &Mat3, b: &Mat3) -> Mat3 {
+    let mut result = [[0.0; 3]; 3];
+    for (i, row) in result.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = (0..3).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    result
+}
+
+const SRGB_TO_XYZ: Mat3 = [[0.4123908, 0.3575843, 0.1804808],
+                           [0.2126390, 0.7151687, 0.0721923],
+                           [0.0193308, 0.1191948, 0.9505322]];
+const XYZ_TO_SRGB: Mat3 = [[ 3.2409699, -1.5373832, -0.4986108],
+                           [-0.9692436,  1.8759675,  0.0415551],
+                           [ 0.0556301, -0.2039770,  1.0569715]];
+const P3_TO_XYZ: Mat3 = [[0.4865709, 0.2656677, 0.1982173],
+                         [0.2289746, 0.6917385, 0.0792869],
+                         [0.0000000, 0.0451134, 1.0439444]];
+const XYZ_TO_P3: Mat3 = [[ 2.4934969, -0.9313836, -0.4027108],
+                         [-0.8294890,  1.7626641,  0.0236247],
+                         [ 0.0358458, -0.0761724,  0.9568845]];
+// to and from XYZ with ACES' own (D60) white
+const AP1_TO_XYZ: Mat3 = [[ 0.6624542, 0.1340042, 0.1561877],
+                          [ 0.2722287, 0.6740818, 0.0536895],
+                          [-0.0055746, 0.0040607, 1.0103391]];
+const XYZ_TO_AP1: Mat3 = [[ 1.6410234, -0.3248033, -0.2364247],
+                          [-0.6636629,  1.6153316,  0.0167563],
+                          [ 0.0117219, -0.0082844,  0.9883949]];
+// bradford chromatic adaptation between the two whites
+const D60_TO_D65: Mat3 = [[ 0.98722400, -0.00611327, 0.01595330],
+                          [-0.00759836,  1.00186000, 0.00533002],
+                          [ 0.00307257, -0.00509595, 1.08168000]];
+const D65_TO_D60: Mat3 = [[ 1.01303000,  0.00610531, -0.01497100],
+                          [ 0.00769823,  0.99816500, -0.00503203],
+                          [-0.00284131,  0.00468516,  0.92450700]];
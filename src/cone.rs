@@ -0,0 +1,74 @@
+// A cone with its base centered on base and its apex height above it, along +y.
+// Wrap it in a Transformed to orient it differently.
+use crate::vec3::{Vec3, Point3};
+use crate::ray::Ray;
+use crate::hittable::{Hittable, HitRecord};
+use crate::interval::Interval;
+use crate::material::Material;
+use crate::aabb::Aabb;
+use crate::roots::solve_quadratic;
+
+use std::sync::Arc;
+use std::f64::consts::PI;
+
+pub struct Cone {
+    base: Point3,
+    radius: f64,    // radius of the base
+    height: f64,
+    capped: bool,   // whether the base disk is closed
+    material: Arc<dyn Material>
+}
+
+impl Cone {
+    pub fn new(base: Point3, radius: f64, height: f64, capped: bool, material: Arc<dyn Material>) -> Cone {
+        Cone{ base, radius, height, capped, material }
+    }
+}
+
+impl Hittable for Cone {
+    fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        let o = ray.origin() - self.base;
+        let d = ray.direction();
+        let mut closest: Option<(f64, Vec3, (f64, f64))> = None;
+        let mut consider = |t: f64, normal: Vec3, uv: (f64, f64)| {
+            if ray_t.surrounds(t) && closest.is_none_or(|c| t < c.0) {
+                closest = Some((t, normal, uv));
+            }
+        };
+
+        // side: x^2 + z^2 = k^2 * (height - y)^2 with 0 <= y <= height,
+        // k is how much the radius shrinks per unit of height
+        let k2 = (self.radius / self.height).powi(2);
+        let h = self.height - o.y();
+        let a = d.x().powi(2) + d.z().powi(2) - k2 * d.y().powi(2);
+        let b = 2.0 * (o.x()*d.x() + o.z()*d.z() + k2 * h * d.y());
+        let c = o.x().powi(2) + o.z().powi(2) - k2 * h * h;
+        for t in solve_quadratic(a, b, c) {
+            let p = o + t * d;
+            if (0.0..=self.height).contains(&p.y()) {
+                // gradient of the implicit surface
+                let normal = Vec3::new(p.x(), k2 * (self.height - p.y()), p.z());
+                if normal.near_zero() { continue; }    // exactly at the apex
+                let uv = ((p.z().atan2(p.x()) + PI) / (2.0*PI), p.y() / self.height);
+                consider(t, normal.unit_vector(), uv);
+            }
+        }
+
+        if self.capped && d.y().abs() > 1e-12 {
+            let t = -o.y() / d.y();
+            let p = o + t * d;
+            let r2 = p.x().powi(2) + p.z().powi(2);
+            if r2 <= self.radius.powi(2) {
+                let uv = ((p.z().atan2(p.x()) + PI) / (2.0*PI), r2.sqrt() / self.radius);
+                consider(t, Vec3::new(0.0, -1.0, 0.0), uv);
+            }
+        }
+
+        let (t, normal, uv) = closest?;
+        Some(HitRecord::new(ray, t, normal, uv, self.material.clone()))
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        Aabb::new(self.base + Vec3::new(-self.radius, 0.0, -self.radius), self.base + Vec3::new(self.radius, self.height, self.radius))
+    }
+}
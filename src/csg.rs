@@ -0,0 +1,173 @@
+// Constructive solid geometry: combine two closed objects into a new one
+// by union, intersection or difference, e.g. a lens is the intersection
+// of two spheres and a hollow cube is a box minus a sphere.
+use crate::ray::Ray;
+use crate::hittable::{Hittable, HitRecord, Span};
+use crate::interval::Interval;
+use crate::aabb::Aabb;
+
+use std::sync::Arc;
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum CsgOp {
+    Union,
+    Intersection,
+    Difference      // left minus right
+}
+
+impl CsgOp {
+    fn inside(&self, in_left: bool, in_right: bool) -> bool {
+        match self {
+            CsgOp::Union        => in_left || in_right,
+            CsgOp::Intersection => in_left && in_right,
+            CsgOp::Difference   => in_left && !in_right
+        }
+    }
+}
+
+pub struct Csg {
+    op: CsgOp,
+    left: Arc<dyn Hittable>,
+    right: Arc<dyn Hittable>,
+    bbox: Aabb
+}
+
+impl Csg {
+    pub fn new(op: CsgOp, left: Arc<dyn Hittable>, right: Arc<dyn Hittable>) -> Csg {
+        let (left_box, right_box) = (left.bounding_box(), right.bounding_box());
+        let bbox = match op {
+            CsgOp::Union        => left_box.union(&right_box),
+            CsgOp::Intersection => left_box.intersection(&right_box),
+            CsgOp::Difference   => left_box
+        };
+        Csg{ op, left, right, bbox }
+    }
+    pub fn union(left: Arc<dyn Hittable>, right: Arc<dyn Hittable>) -> Csg {
+        Csg::new(CsgOp::Union, left, right)
+    }
+    pub fn intersection(left: Arc<dyn Hittable>, right: Arc<dyn Hittable>) -> Csg {
+        Csg::new(CsgOp::Intersection, left, right)
+    }
+    pub fn difference(left: Arc<dyn Hittable>, right: Arc<dyn Hittable>) -> Csg {
+        Csg::new(CsgOp::Difference, left, right)
+    }
+}
+
+impl Hittable for Csg {
+    fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        // both operands are swept over the whole ray, skip that for misses
+        self.bbox.clip(ray, ray_t)?;
+        for span in self.spans(ray) {
+            if ray_t.surrounds(span.enter.t) { return Some(span.enter); }
+            if ray_t.surrounds(span.exit.t) { return Some(span.exit); }
+        }
+        None
+    }
+
+    fn spans(&self, ray: &Ray) -> Vec<Span> {
+        if self.bbox.clip(ray, Interval::new(f64::NEG_INFINITY, f64::INFINITY)).is_none() { return vec![]; }
+
+        // sweep over the boundaries of both operands in order of t,
+        // (t, is left operand, is entering, record)
+        let mut events = vec![];
+        for (is_left, object) in [(true, &self.left), (false, &self.right)] {
+            for span in object.spans(ray) {
+                events.push((span.enter.t, is_left, true, span.enter));
+                events.push((span.exit.t, is_left, false, span.exit));
+            }
+        }
+        events.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let (mut in_left, mut in_right) = (false, false);
+        let mut enter: Option<HitRecord> = None;
+        let mut spans = vec![];
+        for (_, is_left, entering, mut record) in events {
+            let was_inside = self.op.inside(in_left, in_right);
+            if is_left { in_left = entering; } else { in_right = entering; }
+            let inside = self.op.inside(in_left, in_right);
+            if inside == was_inside { continue; }
+
+            // the recorded normal already faces the ray, only whether this is
+            // an entry may change (leaving the right operand of a difference
+            // means entering the result)
+            record.from_outside = inside;
+            if inside {
+                enter = Some(record);
+            } else if let Some(entry) = enter.take() {
+                spans.push(Span{ enter: entry, exit: record });
+            }
+        }
+        spans
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sphere::Sphere;
+    use crate::vec3::{Color, Point3, Vec3};
+    use crate::material::Lambertian;
+
+    fn ball(x: f64, radius: f64) -> Arc<dyn Hittable> {
+        Arc::new(Sphere::new(Point3::new(x, 0.0, 0.0), radius, Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)))))
+    }
+    // the spans along the x axis, as x coordinates
+    fn spans(op: CsgOp, left: Arc<dyn Hittable>, right: Arc<dyn Hittable>) -> Vec<(f64, f64)> {
+        let ray = Ray::new(Point3::new(-10.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        Csg::new(op, left, right).spans(&ray).iter()
+                                 .map(|span| (span.enter.t - 10.0, span.exit.t - 10.0))
+                                 .collect()
+    }
+    fn assert_spans(op: CsgOp, left: Arc<dyn Hittable>, right: Arc<dyn Hittable>, expected: &[(f64, f64)]) {
+        let found = spans(op, left, right);
+        assert_eq!(found.len(), expected.len(), "spans {:?}, expected {:?}", found, expected);
+        for (a, b) in found.iter().zip(expected) {
+            assert!((a.0 - b.0).abs() < 1e-9 && (a.1 - b.1).abs() < 1e-9, "spans {:?}, expected {:?}", found, expected);
+        }
+    }
+
+    #[test]
+    fn overlapping_operands() {
+        assert_spans(CsgOp::Union,        ball(0.0, 1.0), ball(1.0, 1.0), &[(-1.0, 2.0)]);
+        assert_spans(CsgOp::Intersection, ball(0.0, 1.0), ball(1.0, 1.0), &[(0.0, 1.0)]);
+        assert_spans(CsgOp::Difference,   ball(0.0, 1.0), ball(1.0, 1.0), &[(-1.0, 0.0)]);
+    }
+
+    #[test]
+    fn disjoint_operands() {
+        assert_spans(CsgOp::Union,        ball(0.0, 1.0), ball(5.0, 1.0), &[(-1.0, 1.0), (4.0, 6.0)]);
+        assert_spans(CsgOp::Intersection, ball(0.0, 1.0), ball(5.0, 1.0), &[]);
+        assert_spans(CsgOp::Difference,   ball(0.0, 1.0), ball(5.0, 1.0), &[(-1.0, 1.0)]);
+    }
+
+    #[test]
+    fn nested_operands() {
+        assert_spans(CsgOp::Union,        ball(0.0, 2.0), ball(0.0, 1.0), &[(-2.0, 2.0)]);
+        assert_spans(CsgOp::Intersection, ball(0.0, 2.0), ball(0.0, 1.0), &[(-1.0, 1.0)]);
+        assert_spans(CsgOp::Difference,   ball(0.0, 2.0), ball(0.0, 1.0), &[(-2.0, -1.0), (1.0, 2.0)]);
+        assert_spans(CsgOp::Difference,   ball(0.0, 1.0), ball(0.0, 2.0), &[]);
+    }
+
+    #[test]
+    fn the_hole_of_a_difference_is_entered_from_outside() {
+        // leaving the inner ball means entering the shell again
+        let shell = Csg::difference(ball(0.0, 2.0), ball(0.0, 1.0));
+        let ray = Ray::new(Point3::new(-10.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        let record = shell.hit(&ray, Interval::new(9.5, f64::INFINITY)).expect("inner wall missed");
+        assert!((record.t - 11.0).abs() < 1e-9);
+        assert!(record.from_outside);
+        assert!(record.normal.x() < 0.0);
+    }
+
+    #[test]
+    fn rays_outside_the_box_miss() {
+        let shell = Csg::union(ball(0.0, 1.0), ball(5.0, 1.0));
+        let ray = Ray::new(Point3::new(-10.0, 3.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        assert!(shell.spans(&ray).is_empty());
+        assert!(shell.hit(&ray, Interval::new(0.001, f64::INFINITY)).is_none());
+    }
+}
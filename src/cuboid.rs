@@ -0,0 +1,168 @@
+// A box, either axis-aligned or rotated to an arbitrary orientation.
+// It's called Cuboid so that it doesn't shadow Rust's own Box.
+use crate::vec3::{Vec3, Point3};
+use crate::mat4::Mat4;
+use crate::ray::Ray;
+use crate::hittable::{Hittable, HitRecord};
+use crate::interval::Interval;
+use crate::material::Material;
+use crate::aabb::Aabb;
+
+use std::sync::Arc;
+
+pub struct Cuboid {
+    center: Point3,
+    axes: [Vec3; 3],    // unit local x, y, z directions
+    half_size: Vec3,
+    material: Arc<dyn Material>
+}
+
+impl Cuboid {
+    // axis-aligned box spanned by two opposite corners
+    pub fn new(a: Point3, b: Point3, material: Arc<dyn Material>) -> Cuboid {
+        let min = Point3::new(a.x().min(b.x()), a.y().min(b.y()), a.z().min(b.z()));
+        let max = Point3::new(a.x().max(b.x()), a.y().max(b.y()), a.z().max(b.z()));
+        Cuboid::oriented((min + max) / 2.0, (max - min) / 2.0, Mat4::identity(), material)
+    }
+
+    // box of the given half size around center, rotated by rotation
+    // (only the rotational part of the matrix is used: scale is dropped and
+    // a sheared matrix gets its axes made perpendicular again, x first)
+    pub fn oriented(center: Point3, half_size: Vec3, rotation: Mat4, material: Arc<dyn Material>) -> Cuboid {
+        assert!(half_size.x() > 0.0 && half_size.y() > 0.0 && half_size.z() > 0.0, "box is flat or empty!");
+        let column = |axis: Vec3| rotation.transform_vector(axis);
+        let (x, y, z) = (column(Vec3::new(1.0, 0.0, 0.0)), column(Vec3::new(0.0, 1.0, 0.0)), column(Vec3::new(0.0, 0.0, 1.0)));
+        assert!(x.cross(&y).dot(&z) != 0.0, "box rotation is singular!");
+        // gram-schmidt, z keeps the side it was on so mirroring stays mirroring
+        let x = x.unit_vector();
+        let y = (y - y.dot(&x) * x).unit_vector();
+        let mut normal = x.cross(&y);
+        if normal.dot(&z) < 0.0 { normal = -normal; }
+        Cuboid{ center, axes: [x, y, normal], half_size, material }
+    }
+}
+
+impl Hittable for Cuboid {
+    fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        // slab test in the box's local frame, remember which slab
+        // gives the entering and the exiting t
+        let offset = ray.origin() - self.center;
+        let mut t_enter = (f64::NEG_INFINITY, 0, 0.0);   // (t, axis, sign of outside normal)
+        let mut t_exit  = (f64::INFINITY, 0, 0.0);
+
+        for (i, axis) in self.axes.iter().enumerate() {
+            let o = offset.dot(axis);
+            let d = ray.direction().dot(axis);
+            let h = self.half_size[i];
+
+            if d.abs() < 1e-12 {
+                // parallel to this slab, either always inside it or never
+                if o.abs() > h { return None; }
+                continue;
+            }
+            let t0 = (-h - o) / d;
+            let t1 = ( h - o) / d;
+            let (near, far) = if t0 < t1 { (t0, t1) } else { (t1, t0) };
+            // entering through the face the ray travels against
+            if near > t_enter.0 { t_enter = (near, i, -d.signum()); }
+            if far  < t_exit.0  { t_exit  = (far, i, d.signum()); }
+        }
+        // touching an edge or corner exactly still counts as a hit
+        if t_enter.0 > t_exit.0 { return None; }
+
+        // ray starting inside the box only hits it on the way out
+        let (t, axis, sign) = if ray_t.surrounds(t_enter.0) { t_enter }
+                              else if ray_t.surrounds(t_exit.0) { t_exit }
+                              else { return None; };
+
+        // uv are the two remaining local coordinates on the hit face, in [0, 1]
+        let local = ray.at(t) - self.center;
+        let (i, j) = ((axis + 1) % 3, (axis + 2) % 3);
+        let uv = ((local.dot(&self.axes[i]) / self.half_size[i] + 1.0) / 2.0,
+                  (local.dot(&self.axes[j]) / self.half_size[j] + 1.0) / 2.0);
+
+        Some(HitRecord::new(ray, t, sign * self.axes[axis], uv, self.material.clone()))
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let mut extent = Vec3::new(0.0, 0.0, 0.0);
+        for (k, axis) in self.axes.iter().enumerate() {
+            for i in 0..3 { extent[i] += axis[i].abs() * self.half_size[k]; }
+        }
+        Aabb::new(self.center - extent, self.center + extent)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec3::Color;
+    use crate::material::Lambertian;
+
+    // the unit cube from the origin to (1, 1, 1)
+    fn shoot(origin: Point3, direction: Vec3) -> Option<HitRecord> {
+        let cube = Cuboid::new(Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 1.0, 1.0),
+                               Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))));
+        cube.hit(&Ray::new(origin, direction), Interval::new(0.001, f64::INFINITY))
+    }
+
+    #[test]
+    fn edges_and_corners_are_hit() {
+        // along the top face onto the edge it shares with the x = 1 face
+        let record = shoot(Point3::new(2.0, 1.0, 0.5), Vec3::new(-1.0, 0.0, 0.0)).expect("edge missed");
+        assert_eq!(record.t, 1.0);
+        assert_eq!((record.normal.x(), record.normal.y(), record.normal.z()), (1.0, 0.0, 0.0));
+        // grazing the same edge diagonally, in and out at the same point
+        let record = shoot(Point3::new(2.0, 0.0, 0.5), Vec3::new(-1.0, 1.0, 0.0)).expect("edge missed");
+        assert_eq!(record.t, 1.0);
+        // onto the corner
+        let record = shoot(Point3::new(2.0, 2.0, 2.0), Vec3::new(-1.0, -1.0, -1.0)).expect("corner missed");
+        assert_eq!(record.t, 1.0);
+        assert!(record.from_outside);
+    }
+
+    #[test]
+    fn just_past_an_edge_misses() {
+        assert!(shoot(Point3::new(2.0, 1.0 + 1e-9, 0.5), Vec3::new(-1.0, 0.0, 0.0)).is_none());
+        assert!(shoot(Point3::new(2.0, 1e-9, 0.5), Vec3::new(-1.0, 1.0, 0.0)).is_none());
+    }
+
+    #[test]
+    fn parallel_rays_outside_a_slab_miss() {
+        assert!(shoot(Point3::new(-1.0, 2.0, 0.5), Vec3::new(1.0, 0.0, 0.0)).is_none());
+        assert!(shoot(Point3::new(0.5, 0.5, -1.0 - 1e-9), Vec3::new(1.0, 0.0, 0.0)).is_none());
+    }
+
+    #[test]
+    fn normal_faces_the_ray_from_either_side() {
+        let outside = shoot(Point3::new(-1.0, 0.5, 0.5), Vec3::new(1.0, 0.0, 0.0)).unwrap();
+        assert!(outside.from_outside);
+        assert_eq!(outside.t, 1.0);
+        assert_eq!(outside.normal.x(), -1.0);
+        let inside = shoot(Point3::new(0.5, 0.5, 0.5), Vec3::new(1.0, 0.0, 0.0)).unwrap();
+        assert!(!inside.from_outside);
+        assert_eq!(inside.t, 0.5);
+        assert_eq!(inside.normal.x(), -1.0);
+    }
+
+    #[test]
+    fn sheared_axes_are_made_orthonormal() {
+        let mut shear = [[1.0, 0.0, 0.0, 0.0], [0.0, 1.0, 0.0, 0.0], [0.0, 0.0, 1.0, 0.0], [0.0, 0.0, 0.0, 1.0]];
+        shear[0][1] = 0.5;
+        shear[2][2] = -3.0;
+        let cube = Cuboid::oriented(Point3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 1.0, 1.0), Mat4::new(shear),
+                                    Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))));
+        for i in 0..3 {
+            assert!((cube.axes[i].length() - 1.0).abs() < 1e-12);
+            for j in 0..i { assert!(cube.axes[i].dot(&cube.axes[j]).abs() < 1e-12); }
+        }
+        assert_eq!(cube.axes[2].z(), -1.0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn flat_boxes_are_refused() {
+        Cuboid::new(Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 0.0, 1.0),
+                    Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))));
+    }
+}
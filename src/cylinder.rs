@@ -0,0 +1,73 @@
+// A cylinder standing on its base center, along the +y axis.
+// Wrap it in a Transformed to orient it differently.
+use crate::vec3::{Vec3, Point3};
+use crate::ray::Ray;
+use crate::hittable::{Hittable, HitRecord};
+use crate::interval::Interval;
+use crate::material::Material;
+use crate::aabb::Aabb;
+use crate::roots::solve_quadratic;
+
+use std::sync::Arc;
+use std::f64::consts::PI;
+
+pub struct Cylinder {
+    base: Point3,
+    radius: f64,
+    height: f64,
+    capped: bool,   // an uncapped cylinder is an open tube
+    material: Arc<dyn Material>
+}
+
+impl Cylinder {
+    pub fn new(base: Point3, radius: f64, height: f64, capped: bool, material: Arc<dyn Material>) -> Cylinder {
+        Cylinder{ base, radius, height, capped, material }
+    }
+}
+
+impl Hittable for Cylinder {
+    fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        let o = ray.origin() - self.base;
+        let d = ray.direction();
+        // closest candidate so far: (t, outside normal, uv)
+        let mut closest: Option<(f64, Vec3, (f64, f64))> = None;
+        let mut consider = |t: f64, normal: Vec3, uv: (f64, f64)| {
+            if ray_t.surrounds(t) && closest.is_none_or(|c| t < c.0) {
+                closest = Some((t, normal, uv));
+            }
+        };
+
+        // side: x^2 + z^2 = r^2 with 0 <= y <= height
+        let a = d.x().powi(2) + d.z().powi(2);
+        let b = 2.0 * (o.x()*d.x() + o.z()*d.z());
+        let c = o.x().powi(2) + o.z().powi(2) - self.radius.powi(2);
+        for t in solve_quadratic(a, b, c) {
+            let p = o + t * d;
+            if (0.0..=self.height).contains(&p.y()) {
+                // u goes around the axis, v goes up from the base
+                let uv = ((p.z().atan2(p.x()) + PI) / (2.0*PI), p.y() / self.height);
+                consider(t, Vec3::new(p.x(), 0.0, p.z()) / self.radius, uv);
+            }
+        }
+
+        // caps: disks at y = 0 and y = height
+        if self.capped && d.y().abs() > 1e-12 {
+            for (y, normal) in [(0.0, Vec3::new(0.0, -1.0, 0.0)), (self.height, Vec3::new(0.0, 1.0, 0.0))] {
+                let t = (y - o.y()) / d.y();
+                let p = o + t * d;
+                let r2 = p.x().powi(2) + p.z().powi(2);
+                if r2 <= self.radius.powi(2) {
+                    let uv = ((p.z().atan2(p.x()) + PI) / (2.0*PI), r2.sqrt() / self.radius);
+                    consider(t, normal, uv);
+                }
+            }
+        }
+
+        let (t, normal, uv) = closest?;
+        Some(HitRecord::new(ray, t, normal, uv, self.material.clone()))
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        Aabb::new(self.base + Vec3::new(-self.radius, 0.0, -self.radius), self.base + Vec3::new(self.radius, self.height, self.radius))
+    }
+}
@@ -0,0 +1,122 @@
+// Denoising after the render, with the edge-avoiding à-trous wavelet filter
+// (Dammertz et al. 2010). Each iteration blurs every pixel with a 5x5
+// B3-spline kernel whose taps are 2^iteration pixels apart, so a few
+// iterations reach far without many taps. Taps only count as much as they
+// look like the same thing:
+//   color: differences well within the pixel's noise (the standard error the
+//          film kept) are blurred away, bigger ones are kept as detail
+//   normal, albedo, depth: from the first-hit AOVs, so edges between
+//          objects and textures stay sharp. Without AOVs only color is used.
+// The color is divided by the albedo before filtering and multiplied back
+// after, so the filter only has to smooth the lighting.
+use crate::vec3::{Vec3, Color};
+use crate::film::Film;
+use crate::aov::Aov;
+
+const KERNEL: [f64; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+const MIN_ALBEDO: f64 = 1e-3;   // don't divide by (almost) black albedos
+const MIN_SIGMA: f64 = 1e-4;
+
+pub struct Denoiser {
+    iterations: u32,
+    sigma_color: f64,   // in standard errors of the pixel, halved every iteration
+    normal_power: f64,  // the cosine between normals to this power
+    sigma_albedo: f64,
+    sigma_depth: f64    // relative to the pixel's depth, per pixel of tap distance
+}
+
+impl Denoiser {
+    // strength scales how much color difference gets blurred, 1 is a good start
+    pub fn new(iterations: u32, strength: f64) -> Denoiser {
+        Denoiser{ iterations, sigma_color: 4.0 * strength, normal_power: 64.0, sigma_albedo: 0.1, sigma_depth: 0.02 }
+    }
+
+    pub fn denoise(&self, film: &Film) -> Film {
+        let (width, height) = (film.width(), film.height());
+        let index = |row: u32, col: u32| row as usize * width as usize + col as usize;
+        let aovs = film.aovs();
+        let guide = |aov: Aov| -> Option<Vec<Vec3>> {
+            aovs.map(|aovs| (0..height).flat_map(|row| (0..width).map(move |col| aovs.value(row, col, aov))).collect())
+        };
+        let (normals, albedos, depths) = (guide(Aov::Normal), guide(Aov::Albedo), guide(Aov::Depth));
+
+        let demodulate = |color: Color, albedo: Option<Color>, divide: bool| {
+            let Some(albedo) = albedo else { return color; };
+            let mut result = color;
+            for k in 0..3 {
+                if albedo[k] > MIN_ALBEDO {
+                    result[k] = if divide { color[k] / albedo[k] } else { color[k] * albedo[k] };
+                }
+            }
+            result
+        };
+        let albedo_at = |i: usize| albedos.as_ref().map(|albedos| albedos[i]);
+
+        let mut colors = vec![];
+        let mut deviations = vec![];
+        for row in 0..height {
+            for col in 0..width {
+                let albedo = albedo_at(index(row, col));
+                colors.push(demodulate(film.pixel(row, col), albedo, true));
+                let scale = albedo.map_or(1.0, |albedo| film.working_space().luminance(albedo).max(MIN_ALBEDO));
+                deviations.push(film.standard_error(row, col) / scale);
+            }
+        }
+
+        for iteration in 0..self.iterations {
+            let step = 1_i64 << iteration;
+            let sigma_scale = self.sigma_color / (1_u64 << iteration) as f64;
+            let mut filtered = colors.clone();
+            for row in 0..height {
+                for col in 0..width {
+                    let p = index(row, col);
+                    let sigma = (sigma_scale * deviations[p]).max(MIN_SIGMA);
+                    let mut sum = Color::new(0.0, 0.0, 0.0);
+                    let mut weights = 0.0;
+                    for (dy, ky) in KERNEL.iter().enumerate() {
+                        for (dx, kx) in KERNEL.iter().enumerate() {
+                            let r = row as i64 + (dy as i64 - 2) * step;
+                            let c = col as i64 + (dx as i64 - 2) * step;
+                            if r < 0 || c < 0 || r >= height as i64 || c >= width as i64 { continue; }
+                            let q = index(r as u32, c as u32);
+
+                            let difference = colors[p] - colors[q];
+                            let mut weight = kx * ky * (-difference.length_squared() / (2.0 * sigma * sigma)).exp();
+                            if let (Some(normals), Some(albedos), Some(depths)) = (&normals, &albedos, &depths) {
+                                weight *= self.normal_weight(normals[p], normals[q])
+                                        * (-(albedos[p] - albedos[q]).length_squared()
+                                           / (self.sigma_albedo * self.sigma_albedo)).exp()
+                                        * self.depth_weight(depths[p].x(), depths[q].x(), step);
+                            }
+                            sum += weight * colors[q];
+                            weights += weight;
+                        }
+                    }
+                    // the pixel itself always counts, weights can't be 0
+                    filtered[p] = sum / weights;
+                }
+            }
+            colors = filtered;
+        }
+
+        let colors: Vec<Color> = colors.iter().enumerate().map(|(i, &color)| demodulate(color, albedo_at(i), false)).collect();
+        film.with_pixels(&colors)
+    }
+
+    // misses have no normal, they only match other misses
+    fn normal_weight(&self, p: Vec3, q: Vec3) -> f64 {
+        let (p_length, q_length) = (p.length(), q.length());
+        if p_length == 0.0 || q_length == 0.0 {
+            return if p_length == q_length { 1.0 } else { 0.0 };
+        }
+        (p.dot(&q) / (p_length * q_length)).max(0.0).powf(self.normal_power)
+    }
+
+    // misses are infinitely far
+    fn depth_weight(&self, p: f64, q: f64, step: i64) -> f64 {
+        if !p.is_finite() || !q.is_finite() {
+            return if p.is_finite() == q.is_finite() { 1.0 } else { 0.0 };
+        }
+        (-(p - q).abs() / (self.sigma_depth * p * step as f64 + MIN_SIGMA)).exp()
+    }
+}
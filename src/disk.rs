@@ -0,0 +1,106 @@
+// A flat circular disk, its outside is the side the normal points to.
+use crate::vec3::{Vec3, Point3};
+use crate::ray::Ray;
+use crate::hittable::{Hittable, HitRecord};
+use crate::interval::Interval;
+use crate::material::Material;
+use crate::aabb::Aabb;
+
+use std::sync::Arc;
+use std::f64::consts::PI;
+
+pub struct Disk {
+    center: Point3,
+    normal: Vec3,
+    radius: f64,
+    tangent: Vec3,
+    bitangent: Vec3,
+    material: Arc<dyn Material>
+}
+
+impl Disk {
+    pub fn new(center: Point3, normal: Vec3, radius: f64, material: Arc<dyn Material>) -> Disk {
+        let normal = normal.unit_vector();
+        let (tangent, bitangent) = normal.orthonormal_basis();
+        Disk{ center, normal, radius, tangent, bitangent, material }
+    }
+}
+
+impl Hittable for Disk {
+    fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        let denom = self.normal.dot(&ray.direction());
+        if denom.abs() < 1e-8 { return None; }
+
+        let t = (self.center - ray.origin()).dot(&self.normal) / denom;
+        if !ray_t.surrounds(t) { return None; }
+
+        // the rim itself counts as a hit
+        let planar = ray.at(t) - self.center;
+        if planar.length_squared() > self.radius.powi(2) { return None; }
+
+        // u goes around the rim, v goes from the center (0) to the rim (1)
+        let phi = planar.dot(&self.bitangent).atan2(planar.dot(&self.tangent));
+        let uv = ((phi + PI) / (2.0*PI), planar.length() / self.radius);
+        Some(HitRecord::new(ray, t, self.normal, uv, self.material.clone()))
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        // the disk reaches radius * sin(angle between normal and axis) along each axis
+        let n = self.normal;
+        let extent = self.radius * Vec3::new((1.0 - n.x()*n.x()).max(0.0).sqrt(),
+                                             (1.0 - n.y()*n.y()).max(0.0).sqrt(),
+                                             (1.0 - n.z()*n.z()).max(0.0).sqrt());
+        Aabb::new(self.center - extent, self.center + extent)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec3::Color;
+    use crate::material::Lambertian;
+
+    // radius 1 around the origin in the z = 0 plane, outside towards +z
+    fn disk() -> Disk {
+        Disk::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0), 1.0,
+                  Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))))
+    }
+    fn shoot(origin: Point3, direction: Vec3) -> Option<HitRecord> {
+        disk().hit(&Ray::new(origin, direction), Interval::new(0.001, f64::INFINITY))
+    }
+    fn down(x: f64, y: f64) -> Option<HitRecord> {
+        shoot(Point3::new(x, y, 1.0), Vec3::new(0.0, 0.0, -1.0))
+    }
+
+    #[test]
+    fn rim_is_hit() {
+        for (x, y) in [(1.0, 0.0), (-1.0, 0.0), (0.0, 1.0), (0.0, -1.0)] {
+            let record = down(x, y).expect("rim missed");
+            assert_eq!(record.t, 1.0);
+            assert_eq!(record.v, 1.0);
+        }
+    }
+
+    #[test]
+    fn just_outside_the_rim_misses() {
+        for (x, y) in [(1.0 + 1e-9, 0.0), (0.0, -1.0 - 1e-9), (0.7072, 0.7072)] {
+            assert!(down(x, y).is_none(), "hit at ({}, {})", x, y);
+        }
+    }
+
+    #[test]
+    fn parallel_rays_miss() {
+        assert!(shoot(Point3::new(-2.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0)).is_none());
+        assert!(shoot(Point3::new(-2.0, 0.0, 0.5), Vec3::new(1.0, 0.0, 0.0)).is_none());
+    }
+
+    #[test]
+    fn normal_faces_the_ray_from_either_side() {
+        let front = shoot(Point3::new(0.5, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0)).unwrap();
+        assert!(front.from_outside);
+        assert_eq!(front.normal.z(), 1.0);
+        let back = shoot(Point3::new(0.5, 0.0, -1.0), Vec3::new(0.0, 0.0, 1.0)).unwrap();
+        assert!(!back.from_outside);
+        assert_eq!(back.normal.z(), -1.0);
+    }
+}
@@ -0,0 +1,316 @@
+// The film collects the samples of a render. Each sample is splatted into
+// every pixel its reconstruction filter reaches, adding the filter-weighted
+// color and the weight, and a pixel's color is their ratio in the end.
+//
+// The film also keeps the running mean and variance of the samples shot
+// through each pixel, so that adaptive sampling can tell when it's done.
+//
+// Sums are kept in fixed point: integer addition gives the same result in
+// any order, so the image stays bit-identical however the threads that
+// splat into neighbouring pixels happen to interleave.
+use crate::vec3::Color;
+use crate::filter::Filter;
+use crate::aov::{AovBuffer, FirstHit};
+use crate::tonemap::DisplayTransform;
+use crate::colorspace::ColorSpace;
+
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+
+const FIXED_POINT: f64 = (1_u64 << 24) as f64;
+const MAX_COLOR: u32 = 255;
+// below this brightness errors are measured as if the pixel was this bright,
+// so that almost black pixels don't need endless samples
+const MIN_LUMINANCE: f64 = 0.01;
+const REPORTED_INVALID: usize = 10; // pixels of NaN or infinite samples kept to report
+// filters with negative lobes can leave a pixel with next to no weight, or
+// less than none, its color would then blow up
+const MIN_PIXEL_WEIGHT: f64 = 1e-3;
+
+#[derive(Copy, Clone, Default)]
+struct FilmPixel {
+    color: [i64; 3],
+    weight: i64
+}
+
+// welford's running mean and variance of a pixel's sample luminance
+#[derive(Copy, Clone, Default)]
+struct PixelStats {
+    samples: u32,
+    mean: f64,
+    m2: f64     // sum of squared differences from the mean
+}
+
+pub struct Film {
+    width: u32,
+    height: u32,
+    filter: Arc<dyn Filter>,
+    pixels: Vec<FilmPixel>,    // row by row from the top
+    stats: Vec<PixelStats>,
+    aovs: Option<AovBuffer>,   // first hits of the camera rays, if asked for
+    display: DisplayTransform, // how write_ppm turns the pixels into 8 bits
+    invalid_count: u64,        // NaN or infinite samples
+    invalid: Vec<(u32, u32)>,  // the first pixels (row, col) that got them, in image order
+    working_space: ColorSpace  // what the pixels' colors are in
+}
+
+impl Film {
+    pub fn new(width: u32, height: u32, filter: Arc<dyn Filter>) -> Film {
+        let size = width as usize * height as usize;
+        Film{ width, height, filter, pixels: vec![FilmPixel::default(); size], stats: vec![PixelStats::default(); size],
+              aovs: None, display: DisplayTransform::default(), invalid_count: 0, invalid: vec![],
+              working_space: ColorSpace::Srgb }
+    }
+
+    fn index(&self, row: u32, col: u32) -> usize {
+        row as usize * self.width as usize + col as usize
+    }
+
+    pub fn set_display(&mut self, display: DisplayTransform) {
+        self.display = display;
+    }
+    pub fn set_working_space(&mut self, working_space: ColorSpace) {
+        self.working_space = working_space;
+    }
+    pub fn working_space(&self) -> ColorSpace {
+        self.working_space
+    }
+
+    pub fn enable_aovs(&mut self) {
+        self.aovs.get_or_insert_with(|| AovBuffer::new(self.width, self.height));
+    }
+    pub fn aovs(&self) -> Option<&AovBuffer> {
+        self.aovs.as_ref()
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    // (x, y) in pixels from the upper left corner of the image,
+    // pixel (row, col) covers [col, col + 1) x [row, row + 1)
+    pub fn add_sample(&mut self, x: f64, y: f64, color: Color) {
+        let radius = self.filter.radius();
+        let first_col = (x - 0.5 - radius).ceil().max(0.0) as u32;
+        let last_col = (x - 0.5 + radius).floor().min(self.width as f64 - 1.0);
+        let first_row = (y - 0.5 - radius).ceil().max(0.0) as u32;
+        let last_row = (y - 0.5 + radius).floor().min(self.height as f64 - 1.0);
+        if last_col < 0.0 || last_row < 0.0 { return; }
+
+        for row in first_row..=last_row as u32 {
+            for col in first_col..=last_col as u32 {
+                let weight = self.filter.evaluate(x - (col as f64 + 0.5), y - (row as f64 + 0.5));
+                if weight == 0.0 { continue; }
+                let index = self.index(row, col);
+                let pixel = &mut self.pixels[index];
+                for k in 0..3 {
+                    pixel.color[k] = pixel.color[k].saturating_add(to_fixed(weight * color[k]));
+                }
+                pixel.weight = pixel.weight.saturating_add(to_fixed(weight));
+            }
+        }
+    }
+
+    // a NaN or infinite sample (a degenerate vector somewhere) would poison
+    // the pixel and every pixel the filter splats it into. It's counted as
+    // black instead, and the first few pixels are remembered to find out
+    // what went wrong. They're the first in image order, whatever order
+    // the threads found them in
+    pub fn check_sample(&mut self, row: u32, col: u32, color: Color) -> Color {
+        if color.x().is_finite() && color.y().is_finite() && color.z().is_finite() { return color; }
+        self.invalid_count += 1;
+        let index = self.invalid.partition_point(|&pixel| pixel <= (row, col));
+        if index < REPORTED_INVALID {
+            self.invalid.insert(index, (row, col));
+            self.invalid.truncate(REPORTED_INVALID);
+        }
+        Color::new(0.0, 0.0, 0.0)
+    }
+
+    // how many samples were NaN or infinite so far, and the first few
+    // pixels (row, col) they were in, once per sample
+    pub fn invalid_samples(&self) -> (u64, &[(u32, u32)]) {
+        (self.invalid_count, &self.invalid)
+    }
+
+    // counts a sample shot through pixel (row, col), samples have to
+    // be recorded in the same order every time to get the same image
+    pub fn record(&mut self, row: u32, col: u32, color: Color) {
+        let index = self.index(row, col);
+        let stats = &mut self.stats[index];
+        let luminance = self.working_space.luminance(color);
+        stats.samples += 1;
+        let delta = luminance - stats.mean;
+        stats.mean += delta / stats.samples as f64;
+        stats.m2 += delta * (luminance - stats.mean);
+    }
+
+    // keeps what the sample hit first, if the film keeps AOVs
+    pub fn record_first_hit(&mut self, row: u32, col: u32, hit: &FirstHit) {
+        if let Some(aovs) = &mut self.aovs {
+            aovs.add(row, col, hit);
+        }
+    }
+
+    pub fn samples(&self, row: u32, col: u32) -> u32 {
+        self.stats[self.index(row, col)].samples
+    }
+
+    // standard error of the pixel's mean luminance,
+    // infinite until there are two samples to compare
+    pub fn standard_error(&self, row: u32, col: u32) -> f64 {
+        let stats = &self.stats[self.index(row, col)];
+        if stats.samples < 2 { return f64::INFINITY; }
+        let variance = stats.m2 / (stats.samples - 1) as f64;
+        (variance / stats.samples as f64).sqrt()
+    }
+
+    // the standard error relative to the mean
+    pub fn relative_error(&self, row: u32, col: u32) -> f64 {
+        let stats = &self.stats[self.index(row, col)];
+        self.standard_error(row, col) / stats.mean.max(MIN_LUMINANCE)
+    }
+
+    // the weighted average of the samples around the pixel, black if none
+    // (or too little of their weight) reached it. Negative lobes can make
+    // it negative too, which is clamped away
+    pub fn pixel(&self, row: u32, col: u32) -> Color {
+        let pixel = &self.pixels[self.index(row, col)];
+        if pixel.weight as f64 <= MIN_PIXEL_WEIGHT * FIXED_POINT { return Color::new(0.0, 0.0, 0.0); }
+        let channel = |k: usize| (pixel.color[k] as f64 / pixel.weight as f64).max(0.0);
+        Color::new(channel(0), channel(1), channel(2))
+    }
+
+    // the same film with the given colors in its pixels (row by row
+    // from the top), e.g. after denoising
+    pub fn with_pixels(&self, colors: &[Color]) -> Film {
+        let pixels = colors.iter().map(|color| {
+            FilmPixel{ color: [to_fixed(color[0]), to_fixed(color[1]), to_fixed(color[2])], weight: to_fixed(1.0) }
+        }).collect();
+        Film{ width: self.width, height: self.height, filter: self.filter.clone(), pixels,
+              stats: self.stats.clone(), aovs: self.aovs.clone(), display: self.display,
+              invalid_count: self.invalid_count, invalid: self.invalid.clone(), working_space: self.working_space }
+    }
+
+    // the image as PPM, converted into the display's output space and tagged
+    // with it in a comment, then through the display transform
+    pub fn write_ppm(&self, out: &mut impl Write) -> io::Result<()> {
+        let output_space = self.display.output_space;
+        writeln!(out, "P3\n# color space: {}\n{} {}\n{}", output_space.name(), self.width, self.height, MAX_COLOR)?;
+        for i in 0..self.height {
+            for j in 0..self.width {
+                let color = self.working_space.convert(self.pixel(i, j), output_space);
+                let [r, g, b] = self.display.to_8bit(color, i, j);
+                write!(out, "{} {} {}", r, g, b)?; 
+                if j == self.width - 1 {
+                    writeln!(out)?;
+                } else {
+                    write!(out, "  ")?;
+                }
+            }
+        }
+        out.flush()
+    }
+
+    // how many samples every pixel got as a grayscale PPM,
+    // white for the pixel that got the most
+    pub fn write_sample_map(&self, out: &mut impl Write) -> io::Result<()> {
+        let most = self.stats.iter().map(|stats| stats.samples).max().unwrap_or(0).max(1);
+        writeln!(out, "P3\n{} {}\n{}", self.width, self.height, MAX_COLOR)?;
+        for row in self.stats.chunks(self.width as usize) {
+            let values: Vec<String> = row.iter().map(|stats| {
+                let value = stats.samples * MAX_COLOR / most;
+                format!("{} {} {}", value, value, value)
+            }).collect();
+            writeln!(out, "{}", values.join("  "))?;
+        }
+        out.flush()
+    }
+
+    // the sums and statistics of every pixel, to carry on with later (see checkpoint.rs)
+    pub(crate) fn write_raw(&self, out: &mut impl Write) -> io::Result<()> {
+        for (pixel, stats) in self.pixels.iter().zip(&self.stats) {
+            for value in pixel.color.iter().chain([&pixel.weight]) {
+                out.write_all(&value.to_le_bytes())?;
+            }
+            out.write_all(&stats.samples.to_le_bytes())?;
+            out.write_all(&stats.mean.to_le_bytes())?;
+            out.write_all(&stats.m2.to_le_bytes())?;
+        }
+        out.write_all(&self.invalid_count.to_le_bytes())?;
+        out.write_all(&(self.invalid.len() as u32).to_le_bytes())?;
+        for &(row, col) in &self.invalid {
+            out.write_all(&row.to_le_bytes())?;
+            out.write_all(&col.to_le_bytes())?;
+        }
+        match &self.aovs {
+            None => out.write_all(&[0]),
+            Some(aovs) => { out.write_all(&[1])?; aovs.write_raw(out) }
+        }
+    }
+    pub(crate) fn read_raw(input: &mut impl Read, width: u32, height: u32, filter: Arc<dyn Filter>) -> io::Result<Film> {
+        let mut film = Film::new(width, height, filter);
+        let mut bytes = [0_u8; 8];
+        for (pixel, stats) in film.pixels.iter_mut().zip(film.stats.iter_mut()) {
+            for value in pixel.color.iter_mut().chain([&mut pixel.weight]) {
+                input.read_exact(&mut bytes)?;
+                *value = i64::from_le_bytes(bytes);
+            }
+            input.read_exact(&mut bytes[..4])?;
+            stats.samples = u32::from_le_bytes(bytes[..4].try_into().unwrap());
+            input.read_exact(&mut bytes)?;
+            stats.mean = f64::from_le_bytes(bytes);
+            input.read_exact(&mut bytes)?;
+            stats.m2 = f64::from_le_bytes(bytes);
+        }
+        input.read_exact(&mut bytes)?;
+        film.invalid_count = u64::from_le_bytes(bytes);
+        let mut read_u32 = |input: &mut dyn Read| -> io::Result<u32> {
+            input.read_exact(&mut bytes[..4])?;
+            Ok(u32::from_le_bytes(bytes[..4].try_into().unwrap()))
+        };
+        for _ in 0..read_u32(input)? {
+            let row = read_u32(input)?;
+            film.invalid.push((row, read_u32(input)?));
+        }
+        input.read_exact(&mut bytes[..1])?;
+        if bytes[0] == 1 {
+            film.aovs = Some(AovBuffer::read_raw(input, width, height)?);
+        }
+        Ok(film)
+    }
+}
+
+fn to_fixed(value: f64) -> i64 {
+    (value * FIXED_POINT).round() as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::Lanczos;
+
+    #[test]
+    fn pixels_only_reached_by_negative_lobes_stay_black() {
+        let mut film = Film::new(4, 1, Arc::new(Lanczos::new(3.0)));
+        film.add_sample(0.9, 0.5, Color::new(0.5, 0.25, 1.0));
+        // the sample's own pixel gets its color back
+        let own = film.pixel(0, 0);
+        assert!((own[0] - 0.5).abs() < 1e-6 && (own[1] - 0.25).abs() < 1e-6 && (own[2] - 1.0).abs() < 1e-6);
+        // 1.6 pixels away the filter is negative, which would otherwise
+        // turn into the sample's color divided by a negative weight
+        assert!(Lanczos::new(3.0).evaluate(0.9 - 2.5, 0.0) < 0.0);
+        let lobe = film.pixel(0, 2);
+        assert_eq!((lobe[0], lobe[1], lobe[2]), (0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn large_films_are_indexed_without_overflow() {
+        // 70000 x 70000 pixels is more than u32::MAX, their index isn't
+        let film = Film { width: 70_000, height: 70_000, ..Film::new(1, 1, Arc::new(crate::filter::BoxFilter::new(0.5))) };
+        assert_eq!(film.index(69_999, 69_999), 70_000 * 70_000 - 1);
+    }
+}
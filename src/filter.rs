@@ -0,0 +1,144 @@
+// Pixel reconstruction filters: every sample is spread over the pixels
+// around it, weighted by the filter at its distance from each pixel's center.
+// A wider filter blurs a little and keeps edges from stair-stepping, filters
+// with negative lobes (mitchell, lanczos) keep the image sharper.
+//
+// All filters here are separable, evaluate(x, y) = f(x) * f(y), with x and y
+// measured in pixels.
+use std::f64::consts::PI;
+
+pub trait Filter: Send + Sync {
+    // how far from a pixel's center samples still count, in pixels
+    fn radius(&self) -> f64;
+    fn evaluate(&self, x: f64, y: f64) -> f64;
+}
+
+// every sample within radius counts the same, radius 0.5 averages
+// exactly the samples inside the pixel
+pub struct BoxFilter {
+    radius: f64
+}
+
+impl BoxFilter {
+    pub fn new(radius: f64) -> BoxFilter {
+        BoxFilter{ radius }
+    }
+}
+
+impl Filter for BoxFilter {
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+    fn evaluate(&self, x: f64, y: f64) -> f64 {
+        if x.abs() <= self.radius && y.abs() <= self.radius { 1.0 } else { 0.0 }
+    }
+}
+
+// falls off linearly to zero at radius
+pub struct Tent {
+    radius: f64
+}
+
+impl Tent {
+    pub fn new(radius: f64) -> Tent {
+        Tent{ radius }
+    }
+}
+
+impl Filter for Tent {
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+    fn evaluate(&self, x: f64, y: f64) -> f64 {
+        (self.radius - x.abs()).max(0.0) * (self.radius - y.abs()).max(0.0)
+    }
+}
+
+// a gaussian shifted down so that it reaches zero at radius
+pub struct Gaussian {
+    radius: f64,
+    sigma: f64
+}
+
+impl Gaussian {
+    pub fn new(radius: f64, sigma: f64) -> Gaussian {
+        Gaussian{ radius, sigma }
+    }
+    fn gaussian(&self, x: f64) -> f64 {
+        (-x * x / (2.0 * self.sigma * self.sigma)).exp()
+    }
+    fn evaluate_1d(&self, x: f64) -> f64 {
+        (self.gaussian(x) - self.gaussian(self.radius)).max(0.0)
+    }
+}
+
+impl Filter for Gaussian {
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+    fn evaluate(&self, x: f64, y: f64) -> f64 {
+        self.evaluate_1d(x) * self.evaluate_1d(y)
+    }
+}
+
+// mitchell-netravali cubic, b = c = 1/3 is what they recommend.
+// Stretched so that its support of 2 fits radius
+pub struct Mitchell {
+    radius: f64,
+    b: f64,
+    c: f64
+}
+
+impl Mitchell {
+    pub fn new(radius: f64, b: f64, c: f64) -> Mitchell {
+        Mitchell{ radius, b, c }
+    }
+    fn evaluate_1d(&self, x: f64) -> f64 {
+        let x = (2.0 * x / self.radius).abs();
+        let (b, c) = (self.b, self.c);
+        let value = if x < 1.0 {
+            (12.0 - 9.0 * b - 6.0 * c) * x * x * x + (-18.0 + 12.0 * b + 6.0 * c) * x * x + (6.0 - 2.0 * b)
+        } else if x < 2.0 {
+            (-b - 6.0 * c) * x * x * x + (6.0 * b + 30.0 * c) * x * x + (-12.0 * b - 48.0 * c) * x + (8.0 * b + 24.0 * c)
+        } else {
+            0.0
+        };
+        value / 6.0
+    }
+}
+
+impl Filter for Mitchell {
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+    fn evaluate(&self, x: f64, y: f64) -> f64 {
+        self.evaluate_1d(x) * self.evaluate_1d(y)
+    }
+}
+
+// sinc windowed by a wider sinc, cut off at radius
+pub struct Lanczos {
+    radius: f64
+}
+
+impl Lanczos {
+    pub fn new(radius: f64) -> Lanczos {
+        Lanczos{ radius }
+    }
+    fn sinc(x: f64) -> f64 {
+        if x.abs() < 1e-5 { 1.0 } else { (PI * x).sin() / (PI * x) }
+    }
+    fn evaluate_1d(&self, x: f64) -> f64 {
+        if x.abs() > self.radius { return 0.0; }
+        Self::sinc(x) * Self::sinc(x / self.radius)
+    }
+}
+
+impl Filter for Lanczos {
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+    fn evaluate(&self, x: f64, y: f64) -> f64 {
+        self.evaluate_1d(x) * self.evaluate_1d(y)
+    }
+}
@@ -2,17 +2,67 @@ use crate::vec3::{Vec3, Point3};
 use crate::ray::Ray;
 use crate::interval::Interval;
 use crate::material::Material;
+use crate::aabb::Aabb;
 
 use std::sync::Arc;
 
-pub trait Hittable {
+pub trait Hittable: Send + Sync {
     fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord>; 
+
+    // a box the whole object fits in
+    fn bounding_box(&self) -> Aabb;
+
+    // every stretch of the (infinite, in both directions) ray line that lies
+    // inside the object, sorted by t. Only meaningful for closed objects,
+    // it's what constructive solid geometry is built on.
+    // By default we walk along the ray from hit to hit, using from_outside
+    // to tell entries from exits, primitives can override it if they know better.
+    fn spans(&self, ray: &Ray) -> Vec<Span> {
+        let mut spans = vec![];
+        let mut enter: Option<HitRecord> = None;
+        let mut t = f64::NEG_INFINITY;
+
+        for _ in 0..MAX_SPAN_WALK {
+            let Some(record) = self.hit(ray, Interval::new(t, f64::INFINITY)) else { break; };
+            t = record.t + 1e-9 * record.t.abs().max(1.0);
+            match (record.from_outside, enter.take()) {
+                (true, _) => { enter = Some(record); }
+                (false, Some(entry)) => { spans.push(Span{ enter: entry, exit: record }); }
+                (false, None) => {}    // exit without entry, the object isn't closed
+            }
+        }
+        spans
+    }
+}
+
+// guards the default span walk against objects that are hit forever
+const MAX_SPAN_WALK: u32 = 64;
+
+// the part of a ray between entering and leaving an object
+pub struct Span {
+    pub enter: HitRecord,
+    pub exit: HitRecord
 }
 
+#[derive(Clone)]
 pub struct HitRecord {
     pub p: Point3,
     pub normal: Vec3,
     pub t: f64,
+    pub u: f64,  // surface coordinates of the hit point
+    pub v: f64,
     pub from_outside: bool,  // record if ray come from outside object
     pub material: Arc<dyn Material>
 }
+
+impl HitRecord {
+    // outside_normal points out of the surface, the recorded normal
+    // is flipped when needed so that it always points against the ray
+    pub fn new(ray: &Ray, t: f64, outside_normal: Vec3, (u, v): (f64, f64),
+               material: Arc<dyn Material>) -> HitRecord {
+        let from_outside = outside_normal.dot(&ray.direction()) <= 0.0;
+        HitRecord{ p: ray.at(t),
+                   normal: if from_outside { outside_normal } else { -outside_normal },
+                   t, u, v, from_outside, material }
+    }
+}
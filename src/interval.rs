@@ -13,6 +13,13 @@ impl Interval {
         Interval{ min, max } 
     }
     
+    pub fn min(&self) -> f64 {
+        self.min
+    }
+    pub fn max(&self) -> f64 {
+        self.max
+    }
+
     pub fn surrounds(&self, x: f64) -> bool {
         self.min < x && x < self.max
     }
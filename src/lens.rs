@@ -0,0 +1,308 @@
+// A camera looking through a real lens: rays start on the film and are traced
+// through every surface of a lens prescription, refracting at each glass
+// boundary, before they leave into the scene. This gives the lens' own
+// bokeh, distortion and vignetting, which a thin lens can't.
+//
+// Lens space is in millimeters, the film sits at z = 0 and the lens and the
+// scene are towards -z. The elements are listed from the front (scene side)
+// to the back (film side).
+use crate::vec3::{Vec3, Point3};
+use crate::projection::{Projection, View, CameraRay};
+
+use std::f64::consts::PI;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+const PUPIL_BUCKETS: usize = 32;    // exit pupil bounds along the film's radius
+const PUPIL_GRID: usize = 96;       // rays per side of the grid each bucket is found from
+const TRANSMISSION_GRID: usize = 64;
+const FOCUS_STEPS: u32 = 64;
+
+// one line of a prescription file
+#[derive(Copy, Clone)]
+pub struct LensElement {
+    pub radius: f64,            // curvature radius, positive when the center is behind (towards the film), 0 for the aperture stop
+    pub thickness: f64,         // distance along the axis to the next surface
+    pub ior: f64,               // of the glass behind the surface, 0 or 1 for air
+    pub aperture_radius: f64
+}
+
+// the area on the rear element that rays from one ring of the film get through,
+// as a box in the lens' x/y plane for film points on the +x axis
+#[derive(Copy, Clone)]
+struct PupilBounds {
+    min: (f64, f64),
+    max: (f64, f64)
+}
+
+pub struct RealisticLens {
+    elements: Vec<LensElement>,
+    film_diagonal: f64,
+    scale: f64,                 // world units per millimeter
+    exit_pupil: Option<Vec<Option<PupilBounds>>>,   // None for rings nothing gets through
+    center_transmission: f64    // how much light reaches the film center, to normalize the image
+}
+
+impl RealisticLens {
+    // film_diagonal is in millimeters (43.27 for 35mm film), scale converts
+    // millimeters to world units and focus_distance is in world units,
+    // measured from the film. With exit_pupil the rays are only shot towards
+    // the part of the rear element they can get through, which takes a moment
+    // to compute but wastes far fewer rays when the lens is stopped down.
+    // Panics if the lens doesn't work, see try_new.
+    pub fn new(elements: Vec<LensElement>, film_diagonal: f64, scale: f64,
+               focus_distance: f64, exit_pupil: bool) -> RealisticLens {
+        RealisticLens::try_new(elements, film_diagonal, scale, focus_distance, exit_pupil)
+                      .unwrap_or_else(|error| panic!("{}!", error))
+    }
+
+    // like new, but says why instead of panicking when the prescription has
+    // no elements, can't focus at focus_distance or lets no light through
+    pub fn try_new(mut elements: Vec<LensElement>, film_diagonal: f64, scale: f64,
+                   focus_distance: f64, exit_pupil: bool) -> Result<RealisticLens, String> {
+        if elements.is_empty() { return Err("a lens needs at least one element".to_string()); }
+        for element in elements.iter_mut() {
+            if element.ior == 0.0 { element.ior = 1.0; }
+        }
+        let mut lens = RealisticLens{ elements, film_diagonal, scale,
+                                      exit_pupil: None, center_transmission: 1.0 };
+        lens.focus(focus_distance / scale)?;
+        lens.center_transmission = lens.transmission(0.0);
+        if lens.center_transmission <= 0.0 {
+            return Err("no light gets through the lens to the film center".to_string());
+        }
+        if exit_pupil {
+            lens.exit_pupil = Some((0..PUPIL_BUCKETS).map(|bucket| lens.pupil_bounds(bucket)).collect());
+        }
+        Ok(lens)
+    }
+
+    // reads a prescription, one element per line:
+    //   radius thickness ior aperture_diameter
+    // blank lines and lines starting with '#' are skipped. The last thickness
+    // is the distance to the film, which is replaced when focusing.
+    pub fn load(path: impl AsRef<Path>, film_diagonal: f64, scale: f64,
+                focus_distance: f64, exit_pupil: bool) -> io::Result<RealisticLens> {
+        let mut elements = vec![];
+        for (number, line) in fs::read_to_string(path)?.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') { continue; }
+            let invalid = || io::Error::new(io::ErrorKind::InvalidData,
+                                            format!("line {} of the lens file should be: radius thickness ior aperture", number + 1));
+            let values = line.split_whitespace().map(|value| value.parse::<f64>())
+                             .collect::<Result<Vec<_>, _>>().map_err(|_| invalid())?;
+            if values.len() != 4 { return Err(invalid()); }
+            elements.push(LensElement{ radius: values[0], thickness: values[1], ior: values[2],
+                                       aperture_radius: values[3] / 2.0 });
+        }
+        if elements.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "lens file has no elements"));
+        }
+        RealisticLens::try_new(elements, film_diagonal, scale, focus_distance, exit_pupil)
+                      .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+    }
+
+    fn rear(&self) -> &LensElement {
+        &self.elements[self.elements.len() - 1]
+    }
+    fn rear_z(&self) -> f64 {
+        -self.rear().thickness
+    }
+
+    // follows a ray from the film out of the front element, None if
+    // it hits an element's rim or the aperture stop, or reflects inside
+    fn trace(&self, mut origin: Point3, mut direction: Vec3) -> Option<(Point3, Vec3)> {
+        direction = direction.unit_vector();
+        let mut z = 0.0;
+        for (i, element) in self.elements.iter().enumerate().rev() {
+            z -= element.thickness;
+            let t = if element.radius == 0.0 {
+                (z - origin.z()) / direction.z()
+            } else {
+                let center = Point3::new(0.0, 0.0, z + element.radius);
+                let oc = origin - center;
+                let b = oc.dot(&direction);
+                let discriminant = b * b - (oc.length_squared() - element.radius * element.radius);
+                if discriminant < 0.0 { return None; }
+                // the ray goes towards -z, a surface bulging to the film is hit first
+                let root = discriminant.sqrt();
+                if element.radius < 0.0 { -b - root } else { -b + root }
+            };
+            if t <= 0.0 { return None; }
+            let p = origin + t * direction;
+            if p.x() * p.x() + p.y() * p.y() > element.aperture_radius * element.aperture_radius { return None; }
+            origin = p;
+
+            if element.radius != 0.0 {
+                let mut normal = (p - Point3::new(0.0, 0.0, z + element.radius)).unit_vector();
+                if normal.dot(&direction) > 0.0 { normal = -normal; }
+                // from the glass behind this surface into the one in front of it
+                let ior_in = element.ior;
+                let ior_out = if i == 0 { 1.0 } else { self.elements[i - 1].ior };
+                let ratio = ior_in / ior_out;
+                let cos = -direction.dot(&normal);
+                if ratio * ratio * (1.0 - cos * cos) > 1.0 { return None; }
+                direction = Vec3::refract(direction, normal, ratio).unit_vector();
+            }
+        }
+        Some((origin, direction))
+    }
+
+    // where a ray leaving the film center slightly off axis crosses the axis
+    // again in front of the lens, as a distance from the film
+    fn focused_distance(&self) -> f64 {
+        let target = Point3::new(0.01 * self.rear().aperture_radius, 0.0, self.rear_z());
+        match self.trace(Point3::new(0.0, 0.0, 0.0), target) {
+            Some((origin, direction)) if direction.x() * origin.x() < 0.0 => {
+                let t = -origin.x() / direction.x();
+                -(origin.z() + t * direction.z())
+            }
+            _ => f64::INFINITY  // the rays don't meet, focused beyond infinity
+        }
+    }
+
+    // moves the film so that a point distance in front of it is sharp,
+    // the further the film is from the lens the closer the focus
+    fn focus(&mut self, distance: f64) -> Result<(), String> {
+        let last = self.elements.len() - 1;
+        let set_film = |lens: &mut RealisticLens, film: f64| {
+            lens.elements[last].thickness = film;
+            lens.focused_distance()
+        };
+        let (mut near, mut far) = (0.0, 1.0);
+        while set_film(self, far) > distance {
+            far *= 2.0;
+            if far >= 1e6 { return Err(format!("the lens can't focus at {} mm", distance)); }
+        }
+        for _ in 0..FOCUS_STEPS {
+            let middle = 0.5 * (near + far);
+            if set_film(self, middle) > distance { near = middle; } else { far = middle; }
+        }
+        set_film(self, far);
+        Ok(())
+    }
+
+    // how much light gets through to film point (r, 0), averaged over the rear element
+    // and including the cos^4 falloff of rays hitting the film at an angle
+    fn transmission(&self, r: f64) -> f64 {
+        let rear = self.rear().aperture_radius;
+        let mut total = 0.0;
+        for i in 0..TRANSMISSION_GRID {
+            for j in 0..TRANSMISSION_GRID {
+                let u = (i as f64 + 0.5) / TRANSMISSION_GRID as f64;
+                let v = (j as f64 + 0.5) / TRANSMISSION_GRID as f64;
+                let (x, y) = Self::disk_point(rear, (u, v));
+                total += self.film_ray(Point3::new(r, 0.0, 0.0), Point3::new(x, y, self.rear_z()))
+                             .map_or(0.0, |(_, weight)| weight);
+            }
+        }
+        total / (TRANSMISSION_GRID * TRANSMISSION_GRID) as f64
+    }
+
+    // traces from a film point towards a point at the rear element,
+    // the weight being cos^4 of the angle the ray leaves the film at
+    fn film_ray(&self, film: Point3, target: Point3) -> Option<((Point3, Vec3), f64)> {
+        let direction = (target - film).unit_vector();
+        let cos2 = direction.z() * direction.z();
+        Some((self.trace(film, direction)?, cos2 * cos2))
+    }
+
+    fn disk_point(radius: f64, (u, v): (f64, f64)) -> (f64, f64) {
+        let r = radius * u.sqrt();
+        let phi = 2.0 * PI * v;
+        (r * phi.cos(), r * phi.sin())
+    }
+
+    // tries a grid of rays over a square around the rear element from the middle
+    // of the bucket's ring and keeps the box around the ones that got through
+    fn pupil_bounds(&self, bucket: usize) -> Option<PupilBounds> {
+        let r = (bucket as f64 + 0.5) / PUPIL_BUCKETS as f64 * self.film_diagonal / 2.0;
+        let half = 1.5 * self.rear().aperture_radius;
+        let cell = 2.0 * half / PUPIL_GRID as f64;
+        let mut bounds: Option<PupilBounds> = None;
+        for i in 0..PUPIL_GRID {
+            for j in 0..PUPIL_GRID {
+                let x = -half + (i as f64 + 0.5) * cell;
+                let y = -half + (j as f64 + 0.5) * cell;
+                if self.trace(Point3::new(r, 0.0, 0.0), Point3::new(x - r, y, self.rear_z())).is_none() { continue; }
+                bounds = Some(match bounds {
+                    None => PupilBounds{ min: (x, y), max: (x, y) },
+                    Some(b) => PupilBounds{ min: (b.min.0.min(x), b.min.1.min(y)), max: (b.max.0.max(x), b.max.1.max(y)) }
+                });
+            }
+        }
+        // the ring has some width and the grid some gaps, be generous
+        bounds.map(|b| PupilBounds{ min: (b.min.0 - cell, b.min.1 - cell), max: (b.max.0 + cell, b.max.1 + cell) })
+    }
+}
+
+impl Projection for RealisticLens {
+    // the field of view follows from the lens and the film size,
+    // the view's theta and focus_dist aren't used
+    fn generate_ray(&self, view: &View, x: f64, y: f64, lens: (f64, f64)) -> Option<CameraRay> {
+        // the lens flips the image, so the film is upside down and mirrored
+        let diagonal = (1.0 + view.aspect_ratio * view.aspect_ratio).sqrt();
+        let film_h = self.film_diagonal / diagonal;
+        let film_w = film_h * view.aspect_ratio;
+        let film = Point3::new((0.5 - x) * film_w, (y - 0.5) * film_h, 0.0);
+
+        let rear = self.rear().aperture_radius;
+        let (target, area) = match &self.exit_pupil {
+            None => {
+                let (px, py) = Self::disk_point(rear, lens);
+                (Point3::new(px, py, self.rear_z()), PI * rear * rear)
+            }
+            Some(pupil) => {
+                // the bounds are for the +x axis, turn them to where the film point is
+                let r = (film.x() * film.x() + film.y() * film.y()).sqrt();
+                let bucket = ((r / (self.film_diagonal / 2.0) * PUPIL_BUCKETS as f64) as usize).min(PUPIL_BUCKETS - 1);
+                let bounds = pupil[bucket]?;
+                let px = bounds.min.0 + lens.0 * (bounds.max.0 - bounds.min.0);
+                let py = bounds.min.1 + lens.1 * (bounds.max.1 - bounds.min.1);
+                let (sin, cos) = if r > 0.0 { (film.y() / r, film.x() / r) } else { (0.0, 1.0) };
+                let area = (bounds.max.0 - bounds.min.0) * (bounds.max.1 - bounds.min.1);
+                (Point3::new(px * cos - py * sin, px * sin + py * cos, self.rear_z()), area)
+            }
+        };
+
+        let ((origin, direction), falloff) = self.film_ray(film, target)?;
+        let weight = falloff * area / (PI * rear * rear) / self.center_transmission;
+        // lens x/y/z lines up with the camera's u/v/w
+        let to_world = |p: Vec3| self.scale * (p.x() * view.u + p.y() * view.v + p.z() * view.w);
+        Some(CameraRay{ origin: view.origin + to_world(origin), direction: to_world(direction), weight })
+    }
+
+    fn has_lens(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dgauss(focus_distance: f64) -> io::Result<RealisticLens> {
+        RealisticLens::load("lenses/dgauss50.txt", 43.27, 0.001, focus_distance, false)
+    }
+
+    #[test]
+    fn the_example_lens_loads() {
+        assert!(dgauss(10.0).is_ok());
+    }
+
+    #[test]
+    fn focusing_closer_than_the_lens_can_is_an_error() {
+        // a millimeter in front of the film
+        let error = dgauss(0.001).err().expect("focused at 1 mm");
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("can't focus"), "{}", error);
+    }
+
+    #[test]
+    fn lenses_without_elements_or_light_are_errors() {
+        assert!(RealisticLens::try_new(vec![], 43.27, 0.001, 10.0, false).is_err());
+        let closed = LensElement{ radius: 50.0, thickness: 50.0, ior: 1.5, aperture_radius: 0.0 };
+        assert!(RealisticLens::try_new(vec![closed], 43.27, 0.001, 10.0, false).is_err());
+    }
+}
@@ -0,0 +1,37 @@
+pub mod vec3;
+pub mod mat4;
+pub mod quat;
+pub mod aabb;
+pub mod ray;
+pub mod sphere;
+pub mod quad;
+pub mod plane;
+pub mod disk;
+pub mod cuboid;
+pub mod cylinder;
+pub mod cone;
+pub mod torus;
+pub mod paraboloid;
+pub mod roots;
+pub mod hittable;
+pub mod hittable_list;
+pub mod transformed;
+pub mod csg;
+pub mod sdf;
+pub mod interval;
+pub mod camera;
+pub mod projection;
+pub mod stereo;
+pub mod lens;
+pub mod aperture;
+pub mod animation;
+pub mod material;
+pub mod sampler;
+pub mod filter;
+pub mod film;
+pub mod aov;
+pub mod denoise;
+pub mod tonemap;
+pub mod colorspace;
+pub mod spectrum;
+pub mod checkpoint;
@@ -1,34 +1,392 @@
-mod vec3;
-mod ray;
-mod sphere;
-mod hittable;
-mod hittable_list;
-mod interval;
-mod camera;
-mod material;
-use vec3::{Point3, Color};
-use sphere::Sphere;
-use hittable_list::HittableList;
-use camera::Camera;
-use material::{Material, Lambertian as Lamber, Metal, Dielectrics as Die};
+use raytracer::vec3::{Point3, Color};
+use raytracer::sphere::Sphere;
+use raytracer::hittable_list::HittableList;
+use raytracer::camera::{Camera, Adaptive, Checkpointing};
+use raytracer::animation::{self, CameraAnimation, Track, Interpolation};
+use raytracer::projection::{Projection, Perspective, Orthographic, Fisheye, Equirectangular};
+use raytracer::stereo::{Stereo, StereoLayout};
+use raytracer::lens::RealisticLens;
+use raytracer::aperture::{Aperture, Circular, Polygonal, ImageAperture};
+use raytracer::sampler::{Sampler, Independent, Stratified, Halton, Sobol, hash};
+use raytracer::filter::{Filter, BoxFilter, Tent, Gaussian, Mitchell, Lanczos};
+use raytracer::aov::Aov;
+use raytracer::denoise::Denoiser;
+use raytracer::tonemap::{DisplayTransform, ToneMapper};
+use raytracer::colorspace::ColorSpace;
+use raytracer::material::{Material, Lambertian as Lamber, Metal, Dielectrics as Die, Dispersive, Dispersion};
 
+use std::fs::File;
+use std::io::{self, BufWriter};
 use std::sync::Arc;
+use std::time::Duration;
 use rand::prelude::*;
 // use std::f64::consts::FRAC_PI_2;
 // use std::f64::consts::FRAC_PI_4;
-use std::f64::consts::FRAC_PI_8;
+use std::f64::consts::{FRAC_PI_8, PI};
 
 const IMAGE_WIDTH: u32  = 200;
 const ASPECT_RATIO: f64 = 16.0 / 9.0;
-const IMAGE_HEIGHT: u32 = (IMAGE_WIDTH as f64 / ASPECT_RATIO) as u32;
+const FPS: f64 = 24.0;
+const TURNTABLE_SECONDS: f64 = 4.0;   // one full turn around the scene
+const INTEROCULAR: f64 = 0.3;         // the scene is a few meters across, eyes are a bit apart
+const CONVERGENCE: f64 = 10.0;
+const FILM_DIAGONAL: f64 = 43.27;    // 35mm film, in millimeters
+const MM_PER_UNIT: f64 = 1000.0;      // one unit of the scene is a meter
+const DENOISE_ITERATIONS: u32 = 5;
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(60);
+
+// usage: raytracer [OPTIONS] > image.ppm
+//   --frames FIRST LAST    render frames of a turntable animation into
+//                          frame_0000.ppm, frame_0001.ppm, ... instead. Not
+//                          with --aov, --denoised, --sample-map or --progressive
+//   --projection NAME      perspective (default), orthographic, fisheye
+//                          or equirectangular
+//   --stereo LAYOUT        left, right, top-bottom or side-by-side eyes,
+//                          omni-directional stereo for equirectangular, the
+//                          image is then sized to give each eye 2:1
+//   --lens FILE            look through the lens prescription in FILE,
+//                          e.g. lenses/dgauss50.txt
+//   --aperture SHAPE       circular (default), a number of aperture blades (3 or more)
+//                          or a PPM image of the aperture
+//   --sampler NAME         independent (default), stratified, halton or sobol
+//   --samples N            samples per pixel, 10 by default (stratified
+//                          rounds it up to a grid)
+//   --filter NAME          pixel filter: box (default), tent, gaussian,
+//                          mitchell or lanczos
+//   --filter-radius R      how many pixels the filter reaches, each
+//                          filter has its own default
+//   --adaptive MIN ERROR   stop sampling pixels once the error of their
+//                          brightness is below ERROR (e.g. 0.05), after at
+//                          least MIN samples; --samples is the most they get
+//   --sample-map FILE      also write how many samples each pixel got
+//   --progressive FILE     render in passes of more and more samples,
+//                          overwriting FILE after each of them
+//   --snapshot-interval S  with --progressive, write FILE at most every S seconds
+//   --time-limit S         stop after S seconds and write what there is,
+//                          pixels are averaged over the samples they got
+//   --aov NAME FILE        also write what the camera rays hit first: depth,
+//                          normal, albedo, position or material-id, as
+//                          floats into a .pfm FILE or to look at as a .ppm.
+//                          Can be given more than once
+//   --exposure EV          brighten (or darken, negative) the image by EV stops
+//   --tonemap NAME         clip (default), reinhard, aces or agx, to
+//                          roll off highlights instead of clipping them
+//   --no-dither            round to 8 bits without dithering
+//   --working-space NAME   srgb (default, or rec709), acescg or display-p3:
+//                          the linear space colors are rendered in
+//   --output-space NAME    srgb (default) or display-p3, the image is
+//                          tagged with it
+//   --spectral             trace wavelengths instead of RGB
+//   --glass NAME           what the big glass sphere is made of: plain
+//                          (default), or dispersive bk7, flint or diamond
+//   --firefly-clamp MAX    scale samples brighter than MAX down to it
+//   --denoised FILE        also write a denoised image into FILE, the raw
+//                          one still goes where it would have
+//   --checkpoint FILE      save the render into FILE now and then, every
+//                          60 seconds unless --checkpoint-interval says otherwise,
+//                          removed when the render is complete (unless
+//                          --progressive). With --frames every frame has its
+//                          own, FILE numbered like the frames
+//   --checkpoint-interval S
+//   --resume               carry on from --checkpoint FILE if it's there,
+//                          refused if the scene or the settings changed
+//   --seed N               seed for the scene and the samples, the same
+//                          seed always renders the same image
+struct Options {
+    frames: Option<(u32, u32)>,
+    projection: Arc<dyn Projection>,
+    panorama: bool,
+    stereo: Option<StereoLayout>,
+    aperture: Arc<dyn Aperture>,
+    sampler: String,
+    samples: u32,
+    seed: u64,
+    filter: String,
+    filter_radius: Option<f64>,
+    adaptive: Option<Adaptive>,
+    sample_map: Option<String>,
+    aovs: Vec<(Aov, String)>,
+    denoised: Option<String>,
+    display: DisplayTransform,
+    firefly_clamp: Option<f64>,
+    working_space: ColorSpace,
+    spectral: bool,
+    glass: Option<Dispersion>,
+    progressive: Option<String>,
+    snapshot_interval: Option<Duration>,
+    time_limit: Option<Duration>,
+    checkpoint: Option<String>,
+    checkpoint_interval: Duration,
+    resume: bool
+}
+
+fn parse_args() -> Options {
+    let mut options = Options{ frames: None, projection: Arc::new(Perspective), panorama: false, stereo: None,
+                               aperture: Arc::new(Circular), sampler: String::from("independent"), samples: 10,
+                               seed: 0, filter: String::from("box"), filter_radius: None,
+                               adaptive: None, sample_map: None, aovs: vec![], denoised: None,
+                               display: DisplayTransform::default(), firefly_clamp: None,
+                               working_space: ColorSpace::Srgb, spectral: false, glass: None,
+                               progressive: None, snapshot_interval: None, time_limit: None,
+                               checkpoint: None, checkpoint_interval: CHECKPOINT_INTERVAL, resume: false };
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().unwrap_or_else(|| panic!("{} needs a value!", name));
+        match arg.as_str() {
+            "--frames" => {
+                let first = value("--frames").parse().expect("FIRST should be a frame number!");
+                let last = value("--frames").parse().expect("LAST should be a frame number!");
+                options.frames = Some((first, last));
+            }
+            "--projection" => {
+                options.projection = match value("--projection").as_str() {
+                    "perspective"     => Arc::new(Perspective),
+                    "orthographic"    => Arc::new(Orthographic::new(6.0)),
+                    "fisheye"         => Arc::new(Fisheye::new(PI)),
+                    "equirectangular" => { options.panorama = true; Arc::new(Equirectangular) }
+                    name => panic!("unknown projection {}!", name)
+                };
+            }
+            "--lens" => {
+                let path = value("--lens");
+                let lens = RealisticLens::load(&path, FILM_DIAGONAL, 1.0 / MM_PER_UNIT, 10.0, true)
+                                         .unwrap_or_else(|e| panic!("fail to read lens {}: {}", path, e));
+                options.projection = Arc::new(lens);
+            }
+            "--aperture" => {
+                let shape = value("--aperture");
+                options.aperture = match shape.parse::<u32>() {
+                    _ if shape == "circular" => Arc::new(Circular),
+                    Ok(blades) if blades < 3 => panic!("--aperture needs at least 3 blades!"),
+                    Ok(blades) => Arc::new(Polygonal::new(blades, 0.0)),
+                    Err(_) => Arc::new(ImageAperture::load(&shape)
+                                           .unwrap_or_else(|e| panic!("fail to read aperture {}: {}", shape, e)))
+                };
+            }
+            "--sampler" => options.sampler = value("--sampler"),
+            "--samples" => {
+                options.samples = value("--samples").parse().expect("N should be a number of samples!");
+                if options.samples == 0 { panic!("--samples needs at least 1 sample!"); }
+            }
+            "--filter" => options.filter = value("--filter"),
+            "--filter-radius" => options.filter_radius = Some(value("--filter-radius").parse().expect("R should be a number!")),
+            "--adaptive" => {
+                let min_samples = value("--adaptive").parse().expect("MIN should be a number of samples!");
+                let max_error = value("--adaptive").parse().expect("ERROR should be a number!");
+                options.adaptive = Some(Adaptive{ min_samples, max_error });
+            }
+            "--sample-map" => options.sample_map = Some(value("--sample-map")),
+            "--aov" => {
+                let name = value("--aov");
+                let aov = Aov::from_name(&name).unwrap_or_else(|| panic!("unknown AOV {}!", name));
+                options.aovs.push((aov, value("--aov")));
+            }
+            "--exposure" => options.display.exposure = value("--exposure").parse().expect("EV should be a number!"),
+            "--tonemap" => {
+                let name = value("--tonemap");
+                options.display.tone_mapper = ToneMapper::from_name(&name).unwrap_or_else(|| panic!("unknown tone mapper {}!", name));
+            }
+            "--no-dither" => options.display.dither = false,
+            "--working-space" => options.working_space = color_space(&value("--working-space")),
+            "--output-space" => {
+                options.display.output_space = match color_space(&value("--output-space")) {
+                    ColorSpace::AcesCg => panic!("acescg is for rendering in, not for screens!"),
+                    space => space
+                };
+            }
+            "--spectral" => options.spectral = true,
+            "--glass" => {
+                options.glass = match value("--glass").as_str() {
+                    "plain"   => None,
+                    "bk7"     => Some(Dispersion::bk7()),
+                    "flint"   => Some(Dispersion::flint()),
+                    "diamond" => Some(Dispersion::diamond()),
+                    name => panic!("unknown glass {}!", name)
+                };
+            }
+            "--firefly-clamp" => {
+                let max: f64 = value("--firefly-clamp").parse().expect("MAX should be a number!");
+                if max <= 0.0 { panic!("MAX should be above 0!"); }
+                options.firefly_clamp = Some(max);
+            }
+            "--denoised" => options.denoised = Some(value("--denoised")),
+            "--progressive" => options.progressive = Some(value("--progressive")),
+            "--snapshot-interval" => {
+                let seconds = value("--snapshot-interval").parse().expect("S should be a number of seconds!");
+                options.snapshot_interval = Some(Duration::from_secs_f64(seconds));
+            }
+            "--time-limit" => {
+                let seconds = value("--time-limit").parse().expect("S should be a number of seconds!");
+                options.time_limit = Some(Duration::from_secs_f64(seconds));
+            }
+            "--checkpoint" => options.checkpoint = Some(value("--checkpoint")),
+            "--checkpoint-interval" => {
+                let seconds = value("--checkpoint-interval").parse().expect("S should be a number of seconds!");
+                options.checkpoint_interval = Duration::from_secs_f64(seconds);
+            }
+            "--resume" => options.resume = true,
+            "--seed" => options.seed = value("--seed").parse().expect("N should be a number!"),
+            "--stereo" => {
+                options.stereo = Some(match value("--stereo").as_str() {
+                    "left"         => StereoLayout::Left,
+                    "right"        => StereoLayout::Right,
+                    "top-bottom"   => StereoLayout::TopBottom,
+                    "side-by-side" => StereoLayout::SideBySide,
+                    name => panic!("unknown stereo layout {}!", name)
+                });
+            }
+            _ => panic!("unknown option {}!", arg)
+        }
+    }
+    if let Some(layout) = options.stereo {
+        options.projection = if options.panorama {
+            Arc::new(Stereo::omnidirectional(INTEROCULAR, CONVERGENCE, layout))
+        } else {
+            Arc::new(Stereo::new(options.projection, INTEROCULAR, CONVERGENCE, layout))
+        };
+    }
+    options.display.seed = options.seed;
+    if options.resume && options.checkpoint.is_none() {
+        panic!("--resume needs --checkpoint FILE!");
+    }
+    // sequences only write the frames
+    if options.frames.is_some() {
+        let single_image = [("--aov", !options.aovs.is_empty()), ("--denoised", options.denoised.is_some()),
+                            ("--sample-map", options.sample_map.is_some()), ("--progressive", options.progressive.is_some())];
+        if let Some((name, _)) = single_image.iter().find(|(_, given)| *given) {
+            panic!("{} can't be used with --frames!", name);
+        }
+    }
+    options
+}
+
+fn color_space(name: &str) -> ColorSpace {
+    ColorSpace::from_name(name).unwrap_or_else(|| panic!("unknown color space {}!", name))
+}
+
+fn new_filter(name: &str, radius: Option<f64>) -> Arc<dyn Filter> {
+    match name {
+        "box"      => Arc::new(BoxFilter::new(radius.unwrap_or(0.5))),
+        "tent"     => Arc::new(Tent::new(radius.unwrap_or(1.0))),
+        "gaussian" => Arc::new(Gaussian::new(radius.unwrap_or(1.5), 0.5)),
+        "mitchell" => Arc::new(Mitchell::new(radius.unwrap_or(2.0), 1.0 / 3.0, 1.0 / 3.0)),
+        "lanczos"  => Arc::new(Lanczos::new(radius.unwrap_or(3.0))),
+        name => panic!("unknown filter {}!", name)
+    }
+}
+
+fn new_sampler(name: &str, samples: u32, seed: u64) -> Arc<dyn Sampler> {
+    match name {
+        "independent" => Arc::new(Independent::new(samples, seed)),
+        "stratified"  => {
+            let x_strata = (samples as f64).sqrt().ceil() as u32;
+            Arc::new(Stratified::new(x_strata, samples.div_ceil(x_strata), seed))
+        }
+        "halton" => Arc::new(Halton::new(samples, seed)),
+        "sobol"  => Arc::new(Sobol::new(samples, seed)),
+        name => panic!("unknown sampler {}!", name)
+    }
+}
 
 fn main() {
+    let options = parse_args();
+    let sampler = new_sampler(&options.sampler, options.samples, options.seed);
+    let filter = new_filter(&options.filter, options.filter_radius);
+    let world_ref = Arc::new(random_scene(options.seed, options.working_space, options.glass));
+    // everything random_scene builds the scene from, for checkpoints
+    let scene = hash(&[options.seed, options.working_space as u64, options.glass.map_or(0, |glass| glass.id())]);
+    // omni-directional stereo needs 2:1 eyes, a square image for top/bottom
+    let aspect_ratio = match options.stereo {
+        Some(layout) if options.panorama => layout.image_aspect_ratio(2.0),
+        _ => ASPECT_RATIO
+    };
+    let new_camera = || {
+        let mut camera = Camera::new(Point3::new(13.0, 2.0, 3.0), Point3::new(0.0, 0.0, 0.0), FRAC_PI_8, 0.6, 10.0);
+        camera.set_projection(options.projection.clone());
+        camera.set_aperture(options.aperture.clone());
+        camera.set_sampler(sampler.clone());
+        camera.set_filter(filter.clone());
+        camera.set_adaptive(options.adaptive);
+        camera.set_time_budget(options.time_limit);
+        camera.set_display(options.display);
+        camera.set_firefly_clamp(options.firefly_clamp);
+        camera.set_working_space(options.working_space);
+        camera.set_spectral(options.spectral);
+        // the denoiser is guided by the AOVs
+        camera.set_aovs(!options.aovs.is_empty() || options.denoised.is_some());
+        camera.set_checkpointing(options.checkpoint.as_ref().map(|path| {
+            Checkpointing{ path: path.clone(), interval: options.checkpoint_interval, resume: options.resume, scene }
+        }));
+        camera.initialize(aspect_ratio, IMAGE_WIDTH);
+        camera
+    };
+
+    if let Some((first, last)) = options.frames {
+        let turntable = turntable();
+        animation::render_sequence(first, last, FPS, "frame_####.ppm", |time, _| {
+            let mut camera = new_camera();
+            turntable.apply(&mut camera, time);
+            (camera, world_ref.clone())
+        }).expect("fail to write frames!");
+        return;
+    }
+
+    let film = match &options.progressive {
+        Some(path) => new_camera().render_progressive(world_ref, path, options.snapshot_interval)
+                                  .expect("fail to write progressive image!"),
+        None => {
+            let film = new_camera().render_film(world_ref).unwrap_or_else(|e| panic!("fail to render: {}", e));
+            film.write_ppm(&mut io::stdout().lock()).expect("fail to write image to stdout!");
+            film
+        }
+    };
+    if let Some(path) = &options.sample_map {
+        film.write_sample_map(&mut BufWriter::new(File::create(path).expect("fail to create sample map!")))
+            .expect("fail to write sample map!");
+    }
+    if let Some(path) = &options.denoised {
+        let denoised = Denoiser::new(DENOISE_ITERATIONS, 1.0).denoise(&film);
+        denoised.write_ppm(&mut BufWriter::new(File::create(path).unwrap_or_else(|e| panic!("fail to create {}: {}", path, e))))
+                .unwrap_or_else(|e| panic!("fail to write {}: {}", path, e));
+    }
+    if let Some(aovs) = film.aovs() {
+        for (aov, path) in &options.aovs {
+            let mut out = BufWriter::new(File::create(path).unwrap_or_else(|e| panic!("fail to create {}: {}", path, e)));
+            if path.ends_with(".pfm") { aovs.write_pfm(&mut out, *aov) } else { aovs.write_ppm(&mut out, *aov) }
+                .unwrap_or_else(|e| panic!("fail to write {}: {}", path, e));
+        }
+    }
+}
+
+// circle around the scene at the default camera's height and distance
+fn turntable() -> CameraAnimation {
+    let radius = Point3::new(13.0, 0.0, 3.0).length();
+    let position = |step: u32| {
+        let angle = (3.0_f64).atan2(13.0) + step as f64 * PI / 4.0;
+        (step as f64 * TURNTABLE_SECONDS / 8.0, Point3::new(radius * angle.cos(), 2.0, radius * angle.sin()))
+    };
+    let (time, start) = position(0);
+    let mut lookfrom = Track::new(Interpolation::CatmullRom, time, start);
+    for step in 1..=8 {
+        let (time, point) = position(step);
+        lookfrom = lookfrom.key(time, point);
+    }
+    CameraAnimation{ lookfrom,
+                     lookat: Track::constant(Point3::new(0.0, 0.0, 0.0)),
+                     theta: Track::constant(FRAC_PI_8),
+                     focus_dist: Track::constant(10.0) }
+}
+
+// the colors are picked in sRGB, and brought into the working space.
+// glass is what the big glass sphere disperses light like, if at all
+fn random_scene(seed: u64, working_space: ColorSpace, glass: Option<Dispersion>) -> HittableList {
+    let srgb = ColorSpace::Srgb;
     let mut world = HittableList::new();
 
-    let ground_material = Arc::new(Lamber::new(Color::new(0.5, 0.5, 0.5)));
+    let ground_material = Arc::new(Lamber::new(srgb.color(0.5, 0.5, 0.5).in_space(working_space)));
     world.add(Arc::new(Sphere::new(Point3::new(0.0,-1000.0,0.0), 1000.0, ground_material))); 
 
-    let mut rng = thread_rng();
+    let mut rng = StdRng::seed_from_u64(seed);
     for a in -11..11 {
         for b in -11..11 {
             let choose_mat: f64 = rng.gen(); // random number in [0, 1)
@@ -39,34 +397,33 @@ fn main() {
                 
                 if choose_mat < 0.8 {
                     // diffuse
-                    let albedo = Color::random(0.0, 1.0) * Color::random(0.0, 1.0);
-                    sphere_material = Arc::new(Lamber::new(albedo));
+                    let albedo = Color::random(&mut rng, 0.0, 1.0) * Color::random(&mut rng, 0.0, 1.0);
+                    sphere_material = Arc::new(Lamber::new(srgb.tag(albedo).in_space(working_space)));
                     world.add(Arc::new(Sphere::new(center, 0.2, sphere_material)));
                 } else if choose_mat < 0.95 {
                     // metal
-                    let albedo = Color::random(0.5, 1.0);
+                    let albedo = Color::random(&mut rng, 0.5, 1.0);
                     let fuzz = rng.gen_range(0.0..=0.5);
-                    sphere_material = Arc::new(Metal::new(albedo, fuzz));
+                    sphere_material = Arc::new(Metal::new(srgb.tag(albedo).in_space(working_space), fuzz));
                     world.add(Arc::new(Sphere::new(center, 0.2, sphere_material)));
                 } else {
                     // glass
-                    sphere_material = Arc::new(Die::new(Color::new(1.0, 1.0, 1.0), 1.5));
+                    sphere_material = Arc::new(Die::new(srgb.color(1.0, 1.0, 1.0).in_space(working_space), 1.5));
                     world.add(Arc::new(Sphere::new(center, 0.2, sphere_material)));
                 }
             }
         }
     }
 
-    let material1 = Arc::new(Die::new(Color::new(1.0, 1.0, 1.0), 1.5));
+    let material1: Arc<dyn Material> = match glass {
+        None => Arc::new(Die::new(srgb.color(1.0, 1.0, 1.0).in_space(working_space), 1.5)),
+        Some(dispersion) => Arc::new(Dispersive::new(srgb.color(1.0, 1.0, 1.0).in_space(working_space), dispersion))
+    };
     world.add(Arc::new(Sphere::new(Point3::new(0.0, 1.0, 0.0), 1.0, material1)));
-    let material2 = Arc::new(Lamber::new(Color::new(0.4, 0.2, 0.1)));
+    let material2 = Arc::new(Lamber::new(srgb.color(0.4, 0.2, 0.1).in_space(working_space)));
     world.add(Arc::new(Sphere::new(Point3::new(-4.0, 1.0, 0.0), 1.0, material2)));
-    let material3 = Arc::new(Metal::new(Color::new(0.7, 0.6, 0.5), 0.0));
+    let material3 = Arc::new(Metal::new(srgb.color(0.7, 0.6, 0.5).in_space(working_space), 0.0));
     world.add(Arc::new(Sphere::new(Point3::new(4.0, 1.0, 0.0), 1.0, material3)));
 
-    let world_ref = Arc::new(world);
-
-    let mut camera = Camera::new(Point3::new(13.0, 2.0, 3.0), Point3::new(0.0, 0.0, 0.0), FRAC_PI_8, 0.6, 10.0);
-    camera.initialize(ASPECT_RATIO, IMAGE_WIDTH);
-    camera.render(world_ref);
+    world
 }
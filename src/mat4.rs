@@ -0,0 +1,182 @@
+// This file defines a 4x4 matrix used for affine transforms.
+// Points are treated as (x, y, z, 1) and vectors as (x, y, z, 0),
+// so translation only affects points.
+use crate::vec3::{Vec3, Point3};
+use crate::quat::Quat;
+
+use std::ops::Mul;
+
+#[derive(Copy, Clone)]
+pub struct Mat4 {
+    m: [[f64; 4]; 4]    // row major, m[row][col]
+}
+
+impl Mat4 {
+    pub fn new(m: [[f64; 4]; 4]) -> Mat4 {
+        Mat4{ m }
+    }
+
+    pub fn identity() -> Mat4 {
+        Mat4::new([[1.0, 0.0, 0.0, 0.0],
+                   [0.0, 1.0, 0.0, 0.0],
+                   [0.0, 0.0, 1.0, 0.0],
+                   [0.0, 0.0, 0.0, 1.0]])
+    }
+
+    pub fn translation(offset: Vec3) -> Mat4 {
+        Mat4::new([[1.0, 0.0, 0.0, offset.x()],
+                   [0.0, 1.0, 0.0, offset.y()],
+                   [0.0, 0.0, 1.0, offset.z()],
+                   [0.0, 0.0, 0.0, 1.0]])
+    }
+
+    pub fn scaling(factor: Vec3) -> Mat4 {
+        Mat4::new([[factor.x(), 0.0, 0.0, 0.0],
+                   [0.0, factor.y(), 0.0, 0.0],
+                   [0.0, 0.0, factor.z(), 0.0],
+                   [0.0, 0.0, 0.0, 1.0]])
+    }
+
+    // rotate counterclockwise around an arbitrary axis, angle in radians
+    // (Rodrigues' rotation formula written as a matrix)
+    pub fn rotation(axis: Vec3, theta: f64) -> Mat4 {
+        let a = axis.unit_vector();
+        let (x, y, z) = (a.x(), a.y(), a.z());
+        let (sin, cos) = theta.sin_cos();
+        let k = 1.0 - cos;
+        Mat4::new([[cos + x*x*k,   x*y*k - z*sin, x*z*k + y*sin, 0.0],
+                   [y*x*k + z*sin, cos + y*y*k,   y*z*k - x*sin, 0.0],
+                   [z*x*k - y*sin, z*y*k + x*sin, cos + z*z*k,   0.0],
+                   [0.0,           0.0,           0.0,           1.0]])
+    }
+    pub fn rotation_x(theta: f64) -> Mat4 {
+        Mat4::rotation(Vec3::new(1.0, 0.0, 0.0), theta)
+    }
+    pub fn rotation_y(theta: f64) -> Mat4 {
+        Mat4::rotation(Vec3::new(0.0, 1.0, 0.0), theta)
+    }
+    pub fn rotation_z(theta: f64) -> Mat4 {
+        Mat4::rotation(Vec3::new(0.0, 0.0, 1.0), theta)
+    }
+
+    pub fn get(&self, row: usize, col: usize) -> f64 {
+        self.m[row][col]
+    }
+
+    pub fn transpose(&self) -> Mat4 {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = self.m[j][i];
+            }
+        }
+        Mat4::new(m)
+    }
+
+    // gauss-jordan elimination with partial pivoting,
+    // returns None if the matrix is singular (e.g. scaled by zero)
+    pub fn inverse(&self) -> Option<Mat4> {
+        let mut a = self.m;
+        let mut inv = Mat4::identity().m;
+
+        for col in 0..4 {
+            let pivot = (col..4).max_by(|&r1, &r2| a[r1][col].abs().total_cmp(&a[r2][col].abs()))?;
+            if a[pivot][col].abs() < 1e-12 { return None; }
+            a.swap(col, pivot);
+            inv.swap(col, pivot);
+
+            let scale = 1.0 / a[col][col];
+            for j in 0..4 {
+                a[col][j] *= scale;
+                inv[col][j] *= scale;
+            }
+            for row in 0..4 {
+                if row == col { continue; }
+                let factor = a[row][col];
+                for j in 0..4 {
+                    a[row][j] -= factor * a[col][j];
+                    inv[row][j] -= factor * inv[col][j];
+                }
+            }
+        }
+
+        Some(Mat4::new(inv))
+    }
+
+    pub fn transform_point(&self, p: Point3) -> Point3 {
+        let m = &self.m;
+        Point3::new(m[0][0]*p.x() + m[0][1]*p.y() + m[0][2]*p.z() + m[0][3],
+                    m[1][0]*p.x() + m[1][1]*p.y() + m[1][2]*p.z() + m[1][3],
+                    m[2][0]*p.x() + m[2][1]*p.y() + m[2][2]*p.z() + m[2][3])
+    }
+    pub fn transform_vector(&self, v: Vec3) -> Vec3 {
+        let m = &self.m;
+        Vec3::new(m[0][0]*v.x() + m[0][1]*v.y() + m[0][2]*v.z(),
+                  m[1][0]*v.x() + m[1][1]*v.y() + m[1][2]*v.z(),
+                  m[2][0]*v.x() + m[2][1]*v.y() + m[2][2]*v.z())
+    }
+
+    // split into translation, rotation and scale so that it can be
+    // interpolated, any shear in the matrix is lost
+    pub fn to_trs(&self) -> Trs {
+        let translation = Vec3::new(self.m[0][3], self.m[1][3], self.m[2][3]);
+        let column = |j: usize| Vec3::new(self.m[0][j], self.m[1][j], self.m[2][j]);
+        let mut scale = Vec3::new(column(0).length(), column(1).length(), column(2).length());
+
+        // gram-schmidt to get a clean orthonormal basis
+        let x = column(0).unit_vector();
+        let y = (column(1) - column(1).dot(&x) * x).unit_vector();
+        let z = x.cross(&y);
+        // a mirroring matrix is a proper rotation with a negative scale
+        if z.dot(&column(2)) < 0.0 { scale[2] = -scale[2]; }
+        let rotation = Mat4::new([[x.x(), y.x(), z.x(), 0.0],
+                                  [x.y(), y.y(), z.y(), 0.0],
+                                  [x.z(), y.z(), z.z(), 0.0],
+                                  [0.0,   0.0,   0.0,   1.0]]);
+        Trs{ translation, rotation: Quat::from_rotation_matrix(&rotation), scale }
+    }
+}
+
+// an affine transform as scale first, then rotation, then translation
+#[derive(Copy, Clone)]
+pub struct Trs {
+    pub translation: Vec3,
+    pub rotation: Quat,
+    pub scale: Vec3
+}
+
+impl Trs {
+    pub fn new(translation: Vec3, rotation: Quat, scale: Vec3) -> Trs {
+        Trs{ translation, rotation, scale }
+    }
+
+    pub fn to_matrix(&self) -> Mat4 {
+        Mat4::translation(self.translation) * self.rotation.to_matrix() * Mat4::scaling(self.scale)
+    }
+    // cheaper and more accurate than inverting to_matrix()
+    pub fn inverse_matrix(&self) -> Mat4 {
+        let inv_scale = Vec3::new(1.0 / self.scale.x(), 1.0 / self.scale.y(), 1.0 / self.scale.z());
+        Mat4::scaling(inv_scale) * self.rotation.to_matrix().transpose() * Mat4::translation(-self.translation)
+    }
+
+    // s = 0 gives a, s = 1 gives b
+    pub fn lerp(a: &Trs, b: &Trs, s: f64) -> Trs {
+        Trs{ translation: (1.0 - s) * a.translation + s * b.translation,
+             rotation: Quat::slerp(a.rotation, b.rotation, s),
+             scale: (1.0 - s) * a.scale + s * b.scale }
+    }
+}
+
+// matrix product, (a * b) applies b first, then a
+impl Mul for Mat4 {
+    type Output = Self;
+    fn mul(self, other: Self) -> Self::Output {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..4).map(|k| self.m[i][k] * other.m[k][j]).sum();
+            }
+        }
+        Mat4::new(m)
+    }
+}
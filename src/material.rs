@@ -1,12 +1,32 @@
 use crate::vec3::{Vec3, Color};
 use crate::ray::Ray;
 use crate::hittable::HitRecord;
+use crate::sampler::{SampleStream, hash};
+use crate::spectrum::{RgbSpectra, SampledWavelengths, SampledSpectrum};
 
-pub trait Material {
+pub trait Material: Send + Sync {
     // this function's responsibility:
     // determine whether this material reflect rays
     // if so, give out the reflected ray and attenuation
-    fn scatter(&self, ray_in: &Ray, record: &HitRecord) -> Option<(Color, Ray)>;
+    // random choices take their numbers from sampler
+    fn scatter(&self, ray_in: &Ray, record: &HitRecord, sampler: &mut SampleStream) -> Option<(Color, Ray)>;
+    // the same in spectral mode, with the attenuation at the sampled
+    // wavelengths. By default scatter's color turned into a spectrum,
+    // materials that treat wavelengths differently may drop all but the hero
+    fn scatter_spectral(&self, ray_in: &Ray, record: &HitRecord, sampler: &mut SampleStream,
+                        wavelengths: &mut SampledWavelengths, spectra: &RgbSpectra) -> Option<(SampledSpectrum, Ray)> {
+        let (attenuation, scattered) = self.scatter(ray_in, record, sampler)?;
+        Some((spectra.reflectance(attenuation, wavelengths), scattered))
+    }
+    // the color of the surface itself, for the albedo output
+    fn albedo(&self) -> Color;
+    // for the material ID output, materials that look the same share it.
+    // Never 0, that's for nothing hit
+    fn id(&self) -> u64;
+}
+
+fn bits(color: Color) -> [u64; 3] {
+    [color.x().to_bits(), color.y().to_bits(), color.z().to_bits()]
 }
 
 #[derive(Copy, Clone)]
@@ -19,11 +39,17 @@ impl Lambertian {
     }
 }
 impl Material for Lambertian {
-    fn scatter(&self, _: &Ray, record: &HitRecord) -> Option<(Color, Ray)> {
-        let direction = Vec3::random_in_unit_sphere() + record.normal; 
-        Some((self.albedo, Ray::new(record.p, 
+    fn scatter(&self, ray_in: &Ray, record: &HitRecord, sampler: &mut SampleStream) -> Option<(Color, Ray)> {
+        let direction = Vec3::sample_unit_sphere(sampler.get_2d()) + record.normal; 
+        Some((self.albedo, ray_in.scattered(record.p, 
             if direction.near_zero() { record.normal } else { direction }))) 
     }
+    fn albedo(&self) -> Color {
+        self.albedo
+    }
+    fn id(&self) -> u64 {
+        hash(&[&[1], &bits(self.albedo)[..]].concat()).max(1)
+    }
 }
 
 #[derive(Copy, Clone)]
@@ -37,14 +63,20 @@ impl Metal {
     }
 }
 impl Material for Metal {
-    fn scatter(&self, ray_in: &Ray, record: &HitRecord) -> Option<(Color, Ray)> {
+    fn scatter(&self, ray_in: &Ray, record: &HitRecord, sampler: &mut SampleStream) -> Option<(Color, Ray)> {
         let direction = ray_in.direction() - 2.0 * record.normal.dot(&ray_in.direction()) * record.normal;
         //let direction = 2.0 * record.normal + ray_in.direction();
-        let r = Ray::new(record.p, direction + self.fuzz*Vec3::random_in_unit_sphere());
+        let r = ray_in.scattered(record.p, direction + self.fuzz*Vec3::sample_unit_sphere(sampler.get_2d()));
         if r.direction().dot(&record.normal) > 0.0 {
             Some((self.albedo, r))
         } else { None }
     }
+    fn albedo(&self) -> Color {
+        self.albedo
+    }
+    fn id(&self) -> u64 {
+        hash(&[&[2], &bits(self.albedo)[..], &[self.fuzz.to_bits()]].concat()).max(1)
+    }
 }
 
 pub struct Dielectrics {
@@ -59,11 +91,11 @@ impl Dielectrics {
         // Use Schlick's approximation for reflectance.
         let mut r0 = (1.0-ref_idx) / (1.0+ref_idx);
         r0 = r0*r0;
-        return r0 + (1.0-r0)*(1.0 - cosine).powi(5);
+        r0 + (1.0-r0)*(1.0 - cosine).powi(5)
     }
 }
 impl Material for Dielectrics {
-    fn scatter(&self, ray_in: &Ray, record: &HitRecord) -> Option<(Color, Ray)> {
+    fn scatter(&self, ray_in: &Ray, record: &HitRecord, sampler: &mut SampleStream) -> Option<(Color, Ray)> {
         let unit_direction = ray_in.direction().unit_vector();
         let from_outside = record.from_outside;
         let refraction_ratio = if from_outside { 1.0 / self.index } else { self.index };
@@ -75,27 +107,111 @@ impl Material for Dielectrics {
         // schlick approximation
         if Self::reflectance(cos_theta, refraction_ratio) > 0.6 {
             let metal = Metal::new(self.albedo, 0.0);
-            return metal.scatter(ray_in, record);
+            return metal.scatter(ray_in, record, sampler);
         }
        
         // we use snell's law to calculate refracted ray,
         // but sometimes this law doesn't work
         match from_outside {
             true => {
-                Some((self.albedo, Ray::new(record.p, refracted_direction)))         
+                Some((self.albedo, ray_in.scattered(record.p, refracted_direction)))         
             }
             false => {
                 let is_reflection = refraction_ratio * (1.0 - cos_theta.powi(2)).sqrt() > 1.0;
                 // if ray from inside, check whether snell's law still work
                 if !is_reflection {
-                    Some((self.albedo, Ray::new(record.p, refracted_direction)))
+                    Some((self.albedo, ray_in.scattered(record.p, refracted_direction)))
                 } else {
                     // otherwise, it's reflection not refraction
                     // we regard it as metal reflection
                     let reflected_direction = unit_direction + 2.0*cos_theta*record.normal;
-                    Some((self.albedo, Ray::new(record.p, reflected_direction)))
+                    Some((self.albedo, ray_in.scattered(record.p, reflected_direction)))
                 }
             }
         }
     }
+    fn albedo(&self) -> Color {
+        self.albedo
+    }
+    fn id(&self) -> u64 {
+        hash(&[&[3], &bits(self.albedo)[..], &[self.index.to_bits()]].concat()).max(1)
+    }
+}
+
+// how a glass' refraction index changes with the wavelength, in µm as the
+// coefficients are usually given
+#[derive(Copy, Clone)]
+pub enum Dispersion {
+    Cauchy{ a: f64, b: f64 },                   // n = a + b / λ²
+    Sellmeier{ b: [f64; 3], c: [f64; 3] }       // n² = 1 + Σ b λ² / (λ² - c)
+}
+
+impl Dispersion {
+    // schott N-BK7, the usual crown glass
+    pub fn bk7() -> Dispersion {
+        Dispersion::Sellmeier{ b: [1.03961212, 0.231792344, 1.01046945],
+                               c: [0.00600069867, 0.0200179144, 103.560653] }
+    }
+    // schott F2, a flint glass, spreads colors about twice as much as BK7
+    pub fn flint() -> Dispersion {
+        Dispersion::Sellmeier{ b: [1.34533359, 0.209073176, 0.937357162],
+                               c: [0.00997743871, 0.0470450767, 111.886764] }
+    }
+    pub fn diamond() -> Dispersion {
+        Dispersion::Sellmeier{ b: [4.3356, 0.3306, 0.0], c: [0.1060 * 0.1060, 0.1750 * 0.1750, 0.0] }
+    }
+
+    // the same for the same coefficients, e.g. to tell scenes apart
+    pub fn id(&self) -> u64 {
+        match self {
+            Dispersion::Cauchy{ a, b } => hash(&[1, a.to_bits(), b.to_bits()]),
+            Dispersion::Sellmeier{ b, c } => {
+                hash(&[&[2], &b.iter().chain(c).map(|value| value.to_bits()).collect::<Vec<u64>>()[..]].concat())
+            }
+        }
+    }
+
+    // lambda in nm
+    pub fn index(&self, lambda: f64) -> f64 {
+        let l2 = (lambda / 1000.0).powi(2);
+        match self {
+            Dispersion::Cauchy{ a, b } => a + b / l2,
+            Dispersion::Sellmeier{ b, c } => (1.0 + (0..3).map(|i| b[i] * l2 / (l2 - c[i])).sum::<f64>()).sqrt()
+        }
+    }
+}
+
+// glass whose refraction index depends on the wavelength, which splits
+// white light into rainbows. That only shows in spectral mode: a ray
+// without a wavelength (RGB mode) is refracted as yellow light would be
+pub struct Dispersive {
+    albedo: Color,
+    dispersion: Dispersion
+}
+impl Dispersive {
+    // the sodium d line glass indices are quoted at, in nm
+    const D_LINE: f64 = 587.6;
+
+    pub fn new(albedo: Color, dispersion: Dispersion) -> Dispersive {
+        Dispersive{ albedo, dispersion }
+    }
+}
+impl Material for Dispersive {
+    fn scatter(&self, ray_in: &Ray, record: &HitRecord, sampler: &mut SampleStream) -> Option<(Color, Ray)> {
+        let index = self.dispersion.index(ray_in.wavelength().unwrap_or(Self::D_LINE));
+        Dielectrics::new(self.albedo, index).scatter(ray_in, record, sampler)
+    }
+    // every wavelength bends its own way, only the hero (the ray's) goes on
+    fn scatter_spectral(&self, ray_in: &Ray, record: &HitRecord, sampler: &mut SampleStream,
+                        wavelengths: &mut SampledWavelengths, spectra: &RgbSpectra) -> Option<(SampledSpectrum, Ray)> {
+        wavelengths.terminate_secondary();
+        let (attenuation, scattered) = self.scatter(ray_in, record, sampler)?;
+        Some((spectra.reflectance(attenuation, wavelengths), scattered))
+    }
+    fn albedo(&self) -> Color {
+        self.albedo
+    }
+    fn id(&self) -> u64 {
+        hash(&[&[4], &bits(self.albedo)[..], &[self.dispersion.id()]].concat()).max(1)
+    }
 }
@@ -0,0 +1,72 @@
+// A paraboloid (dish) with its vertex at vertex, opening along +y,
+// reaching radius at the given height. Wrap it in a Transformed to
+// orient it differently.
+use crate::vec3::{Vec3, Point3};
+use crate::ray::Ray;
+use crate::hittable::{Hittable, HitRecord};
+use crate::interval::Interval;
+use crate::material::Material;
+use crate::aabb::Aabb;
+use crate::roots::solve_quadratic;
+
+use std::sync::Arc;
+use std::f64::consts::PI;
+
+pub struct Paraboloid {
+    vertex: Point3,
+    radius: f64,
+    height: f64,
+    capped: bool,   // whether the top opening is closed
+    material: Arc<dyn Material>
+}
+
+impl Paraboloid {
+    pub fn new(vertex: Point3, radius: f64, height: f64, capped: bool, material: Arc<dyn Material>) -> Paraboloid {
+        Paraboloid{ vertex, radius, height, capped, material }
+    }
+}
+
+impl Hittable for Paraboloid {
+    fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        let o = ray.origin() - self.vertex;
+        let d = ray.direction();
+        let mut closest: Option<(f64, Vec3, (f64, f64))> = None;
+        let mut consider = |t: f64, normal: Vec3, uv: (f64, f64)| {
+            if ray_t.surrounds(t) && closest.is_none_or(|c| t < c.0) {
+                closest = Some((t, normal, uv));
+            }
+        };
+
+        // side: x^2 + z^2 = k * y with 0 <= y <= height
+        let k = self.radius.powi(2) / self.height;
+        let a = d.x().powi(2) + d.z().powi(2);
+        let b = 2.0 * (o.x()*d.x() + o.z()*d.z()) - k * d.y();
+        let c = o.x().powi(2) + o.z().powi(2) - k * o.y();
+        for t in solve_quadratic(a, b, c) {
+            let p = o + t * d;
+            if (0.0..=self.height).contains(&p.y()) {
+                // gradient of the implicit surface, pointing away from the inside
+                let normal = Vec3::new(2.0 * p.x(), -k, 2.0 * p.z()).unit_vector();
+                let uv = ((p.z().atan2(p.x()) + PI) / (2.0*PI), p.y() / self.height);
+                consider(t, normal, uv);
+            }
+        }
+
+        if self.capped && d.y().abs() > 1e-12 {
+            let t = (self.height - o.y()) / d.y();
+            let p = o + t * d;
+            let r2 = p.x().powi(2) + p.z().powi(2);
+            if r2 <= self.radius.powi(2) {
+                let uv = ((p.z().atan2(p.x()) + PI) / (2.0*PI), r2.sqrt() / self.radius);
+                consider(t, Vec3::new(0.0, 1.0, 0.0), uv);
+            }
+        }
+
+        let (t, normal, uv) = closest?;
+        Some(HitRecord::new(ray, t, normal, uv, self.material.clone()))
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        Aabb::new(self.vertex + Vec3::new(-self.radius, 0.0, -self.radius), self.vertex + Vec3::new(self.radius, self.height, self.radius))
+    }
+}
@@ -0,0 +1,44 @@
+// An infinite plane through a point, its outside is the side the normal points to.
+// Useful as a floor instead of a huge sphere.
+use crate::vec3::{Vec3, Point3};
+use crate::ray::Ray;
+use crate::hittable::{Hittable, HitRecord};
+use crate::interval::Interval;
+use crate::material::Material;
+use crate::aabb::Aabb;
+
+use std::sync::Arc;
+
+pub struct Plane {
+    point: Point3,
+    normal: Vec3,
+    tangent: Vec3,      // u and v are measured along these two directions,
+    bitangent: Vec3,    // one unit of world space per unit of uv
+    material: Arc<dyn Material>
+}
+
+impl Plane {
+    pub fn new(point: Point3, normal: Vec3, material: Arc<dyn Material>) -> Plane {
+        let normal = normal.unit_vector();
+        let (tangent, bitangent) = normal.orthonormal_basis();
+        Plane{ point, normal, tangent, bitangent, material }
+    }
+}
+
+impl Hittable for Plane {
+    fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        let denom = self.normal.dot(&ray.direction());
+        if denom.abs() < 1e-8 { return None; }
+
+        let t = (self.point - ray.origin()).dot(&self.normal) / denom;
+        if !ray_t.surrounds(t) { return None; }
+
+        let planar = ray.at(t) - self.point;
+        let uv = (planar.dot(&self.tangent), planar.dot(&self.bitangent));
+        Some(HitRecord::new(ray, t, self.normal, uv, self.material.clone()))
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        Aabb::everything()
+    }
+}
@@ -0,0 +1,123 @@
+// How the camera maps points of the image to rays in the world.
+// Every projection works on the same camera basis that Camera::render
+// sets up from lookfrom, lookat and vup.
+use crate::vec3::{Vec3, Point3};
+
+use std::f64::consts::PI;
+
+// the camera's position and orientation for one render
+#[derive(Copy, Clone)]
+pub struct View {
+    pub origin: Point3,     // lookfrom
+    pub u: Vec3,            // unit vector to the right of the image
+    pub v: Vec3,            // unit vector to the top of the image
+    pub w: Vec3,            // unit vector pointing backwards, the camera looks along -w
+    pub theta: f64,         // vertical field of view
+    pub focus_dist: f64,
+    pub aspect_ratio: f64   // image width / image height
+}
+
+// a ray leaving the camera, weight scales the color it brings back
+// (e.g. less light gets through the edges of a real lens)
+pub struct CameraRay {
+    pub origin: Point3,
+    pub direction: Vec3,
+    pub weight: f64
+}
+
+impl CameraRay {
+    pub fn new(origin: Point3, direction: Vec3) -> CameraRay {
+        CameraRay{ origin, direction, weight: 1.0 }
+    }
+}
+
+pub trait Projection: Send + Sync {
+    // (x, y) is a position on the image, both in [0, 1] starting from the
+    // upper left corner, lens is a uniform random point in [0, 1)^2 for
+    // projections that sample an aperture. Gives None where the projection
+    // doesn't see anything (e.g. outside a fisheye circle)
+    fn generate_ray(&self, view: &View, x: f64, y: f64, lens: (f64, f64)) -> Option<CameraRay>;
+
+    // projections tracing their own lens (see lens.rs) don't get
+    // the camera's thin lens defocus on top
+    fn has_lens(&self) -> bool {
+        false
+    }
+}
+
+// the usual pinhole camera, rays spread from lookfrom through a viewport
+// placed focus_dist in front of it
+pub struct Perspective;
+
+impl Projection for Perspective {
+    fn generate_ray(&self, view: &View, x: f64, y: f64, _lens: (f64, f64)) -> Option<CameraRay> {
+        let viewport_h = 2.0 * view.focus_dist * (view.theta / 2.0).tan();
+        let viewport_w = viewport_h * view.aspect_ratio;
+        let point = view.origin - view.focus_dist * view.w
+                  + (x - 0.5) * viewport_w * view.u + (0.5 - y) * viewport_h * view.v;
+        Some(CameraRay::new(view.origin, point - view.origin))
+    }
+}
+
+// parallel rays, objects keep their size however far away they are
+pub struct Orthographic {
+    height: f64     // how much of the world the image covers vertically
+}
+
+impl Orthographic {
+    pub fn new(height: f64) -> Orthographic {
+        Orthographic{ height }
+    }
+}
+
+impl Projection for Orthographic {
+    fn generate_ray(&self, view: &View, x: f64, y: f64, _lens: (f64, f64)) -> Option<CameraRay> {
+        let width = self.height * view.aspect_ratio;
+        let origin = view.origin + (x - 0.5) * width * view.u + (0.5 - y) * self.height * view.v;
+        Some(CameraRay::new(origin, -view.w))
+    }
+}
+
+// equidistant fisheye, the angle from the view direction grows linearly
+// with the distance from the image center. The image circle touches the
+// shorter side of the image and spans fov (e.g. PI for 180 degrees)
+pub struct Fisheye {
+    fov: f64
+}
+
+impl Fisheye {
+    pub fn new(fov: f64) -> Fisheye {
+        Fisheye{ fov }
+    }
+}
+
+impl Projection for Fisheye {
+    fn generate_ray(&self, view: &View, x: f64, y: f64, _lens: (f64, f64)) -> Option<CameraRay> {
+        // position on the image relative to the circle's radius
+        let scale = 2.0 * view.aspect_ratio.max(1.0);
+        let px = (x - 0.5) * scale;
+        let py = (0.5 - y) * scale / view.aspect_ratio;
+        let r = (px * px + py * py).sqrt();
+        if r > 1.0 { return None; }
+
+        let angle = r * self.fov / 2.0;
+        let phi = py.atan2(px);
+        let direction = angle.sin() * (phi.cos() * view.u + phi.sin() * view.v) - angle.cos() * view.w;
+        Some(CameraRay::new(view.origin, direction))
+    }
+}
+
+// 360 degree latitude-longitude panorama, the image center looks along
+// the view direction, left and right edges look backwards
+pub struct Equirectangular;
+
+impl Projection for Equirectangular {
+    fn generate_ray(&self, view: &View, x: f64, y: f64, _lens: (f64, f64)) -> Option<CameraRay> {
+        let longitude = (x - 0.5) * 2.0 * PI;
+        let latitude = (0.5 - y) * PI;
+        let direction = latitude.cos() * longitude.sin() * view.u
+                      + latitude.sin() * view.v
+                      - latitude.cos() * longitude.cos() * view.w;
+        Some(CameraRay::new(view.origin, direction))
+    }
+}
@@ -0,0 +1,119 @@
+// A parallelogram given by a corner q and two edge vectors u and v,
+// the four corners are q, q+u, q+v and q+u+v.
+// Its outside is the side that u x v points to.
+use crate::vec3::{Vec3, Point3};
+use crate::ray::Ray;
+use crate::hittable::{Hittable, HitRecord};
+use crate::interval::Interval;
+use crate::material::Material;
+use crate::aabb::Aabb;
+
+use std::sync::Arc;
+
+pub struct Quad {
+    q: Point3,
+    u: Vec3,
+    v: Vec3,
+    normal: Vec3,
+    d: f64,     // plane equation: normal . p = d
+    w: Vec3,    // n / (n . n), used to get planar coordinates of a hit point
+    material: Arc<dyn Material>
+}
+
+impl Quad {
+    // u and v must span a parallelogram, not a line or a point
+    pub fn new(q: Point3, u: Vec3, v: Vec3, material: Arc<dyn Material>) -> Quad {
+        let n = u.cross(&v);
+        assert!(n.length_squared() > 0.0, "quad edges are parallel or empty!");
+        let normal = n.unit_vector();
+        Quad{ q, u, v, normal, d: normal.dot(&q), w: n / n.length_squared(), material }
+    }
+}
+
+impl Hittable for Quad {
+    fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        // ray parallel to the plane never hits it
+        let denom = self.normal.dot(&ray.direction());
+        if denom.abs() < 1e-8 { return None; }
+
+        let t = (self.d - self.normal.dot(&ray.origin())) / denom;
+        if !ray_t.surrounds(t) { return None; }
+
+        // express the hit point as q + alpha*u + beta*v,
+        // edges are included so neighbouring quads leave no cracks
+        let planar = ray.at(t) - self.q;
+        let alpha = self.w.dot(&planar.cross(&self.v));
+        let beta = self.w.dot(&self.u.cross(&planar));
+        if !(0.0..=1.0).contains(&alpha) || !(0.0..=1.0).contains(&beta) { return None; }
+
+        Some(HitRecord::new(ray, t, self.normal, (alpha, beta), self.material.clone()))
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        Aabb::new(self.q, self.q + self.u + self.v)
+            .union(&Aabb::new(self.q + self.u, self.q + self.v))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec3::Color;
+    use crate::material::Lambertian;
+
+    // the unit square in the z = 0 plane, outside towards +z
+    fn square() -> Quad {
+        Quad::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0),
+                  Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))))
+    }
+    fn shoot(quad: &Quad, origin: Point3, direction: Vec3) -> Option<HitRecord> {
+        quad.hit(&Ray::new(origin, direction), Interval::new(0.001, f64::INFINITY))
+    }
+    fn down(x: f64, y: f64) -> Option<HitRecord> {
+        shoot(&square(), Point3::new(x, y, 1.0), Vec3::new(0.0, 0.0, -1.0))
+    }
+
+    #[test]
+    fn edges_and_corners_are_hit() {
+        for (x, y) in [(0.0, 0.5), (1.0, 0.5), (0.5, 0.0), (0.5, 1.0)] {
+            let record = down(x, y).expect("edge missed");
+            assert_eq!(record.t, 1.0);
+        }
+        for (x, y) in [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0), (1.0, 1.0)] {
+            let record = down(x, y).expect("corner missed");
+            assert_eq!((record.u, record.v), (x, y));
+        }
+    }
+
+    #[test]
+    fn just_outside_the_edges_misses() {
+        for (x, y) in [(-1e-9, 0.5), (1.0 + 1e-9, 0.5), (0.5, -1e-9), (0.5, 1.0 + 1e-9), (1.0 + 1e-9, 1.0 + 1e-9)] {
+            assert!(down(x, y).is_none(), "hit at ({}, {})", x, y);
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn parallel_edges_are_refused() {
+        Quad::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0), Vec3::new(2.0, 0.0, 0.0),
+                  Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))));
+    }
+
+    #[test]
+    fn parallel_rays_miss() {
+        let quad = square();
+        assert!(shoot(&quad, Point3::new(-1.0, 0.5, 0.0), Vec3::new(1.0, 0.0, 0.0)).is_none());
+        assert!(shoot(&quad, Point3::new(-1.0, 0.5, 0.5), Vec3::new(1.0, 0.0, 0.0)).is_none());
+    }
+
+    #[test]
+    fn normal_faces_the_ray_from_either_side() {
+        let quad = square();
+        let front = shoot(&quad, Point3::new(0.5, 0.5, 1.0), Vec3::new(0.0, 0.0, -1.0)).unwrap();
+        assert!(front.from_outside);
+        assert_eq!(front.normal.z(), 1.0);
+        let back = shoot(&quad, Point3::new(0.5, 0.5, -1.0), Vec3::new(0.0, 0.0, 1.0)).unwrap();
+        assert!(!back.from_outside);
+        assert_eq!(back.normal.z(), -1.0);
+    }
+}
@@ -0,0 +1,76 @@
+// Unit quaternions for rotations that can be interpolated smoothly,
+// which isn't possible by blending rotation matrices element by element.
+use crate::vec3::Vec3;
+use crate::mat4::Mat4;
+
+#[derive(Copy, Clone)]
+pub struct Quat {
+    w: f64,
+    v: Vec3     // imaginary part
+}
+
+impl Quat {
+    pub fn new(w: f64, v: Vec3) -> Quat {
+        Quat{ w, v }
+    }
+    pub fn identity() -> Quat {
+        Quat::new(1.0, Vec3::new(0.0, 0.0, 0.0))
+    }
+    // counterclockwise rotation around axis, angle in radians
+    pub fn from_axis_angle(axis: Vec3, theta: f64) -> Quat {
+        let (sin, cos) = (theta / 2.0).sin_cos();
+        Quat::new(cos, sin * axis.unit_vector())
+    }
+
+    // the matrix must be a pure rotation (orthonormal, no scale)
+    pub fn from_rotation_matrix(m: &Mat4) -> Quat {
+        let r = |i: usize, j: usize| m.get(i, j);
+        let trace = r(0, 0) + r(1, 1) + r(2, 2);
+        // pick the largest component to divide by for numerical stability
+        let q = if trace > 0.0 {
+            let s = 2.0 * (trace + 1.0).sqrt();
+            Quat::new(s / 4.0, Vec3::new(r(2, 1) - r(1, 2), r(0, 2) - r(2, 0), r(1, 0) - r(0, 1)) / s)
+        } else if r(0, 0) > r(1, 1) && r(0, 0) > r(2, 2) {
+            let s = 2.0 * (1.0 + r(0, 0) - r(1, 1) - r(2, 2)).sqrt();
+            Quat::new((r(2, 1) - r(1, 2)) / s, Vec3::new(s / 4.0, (r(0, 1) + r(1, 0)) / s, (r(0, 2) + r(2, 0)) / s))
+        } else if r(1, 1) > r(2, 2) {
+            let s = 2.0 * (1.0 + r(1, 1) - r(0, 0) - r(2, 2)).sqrt();
+            Quat::new((r(0, 2) - r(2, 0)) / s, Vec3::new((r(0, 1) + r(1, 0)) / s, s / 4.0, (r(1, 2) + r(2, 1)) / s))
+        } else {
+            let s = 2.0 * (1.0 + r(2, 2) - r(0, 0) - r(1, 1)).sqrt();
+            Quat::new((r(1, 0) - r(0, 1)) / s, Vec3::new((r(0, 2) + r(2, 0)) / s, (r(1, 2) + r(2, 1)) / s, s / 4.0))
+        };
+        q.normalized()
+    }
+
+    pub fn to_matrix(&self) -> Mat4 {
+        let (w, x, y, z) = (self.w, self.v.x(), self.v.y(), self.v.z());
+        Mat4::new([[1.0 - 2.0*(y*y + z*z), 2.0*(x*y - w*z),       2.0*(x*z + w*y),       0.0],
+                   [2.0*(x*y + w*z),       1.0 - 2.0*(x*x + z*z), 2.0*(y*z - w*x),       0.0],
+                   [2.0*(x*z - w*y),       2.0*(y*z + w*x),       1.0 - 2.0*(x*x + y*y), 0.0],
+                   [0.0,                   0.0,                   0.0,                   1.0]])
+    }
+
+    pub fn dot(&self, other: &Quat) -> f64 {
+        self.w * other.w + self.v.dot(&other.v)
+    }
+    pub fn normalized(&self) -> Quat {
+        let length = self.dot(self).sqrt();
+        Quat::new(self.w / length, self.v / length)
+    }
+
+    // spherical linear interpolation, s = 0 gives a and s = 1 gives b,
+    // always turning the short way around
+    pub fn slerp(a: Quat, b: Quat, s: f64) -> Quat {
+        let mut cos = a.dot(&b);
+        let b = if cos < 0.0 { cos = -cos; Quat::new(-b.w, -b.v) } else { b };
+        // nearly the same rotation, plain lerp avoids dividing by sin(~0)
+        let (ka, kb) = if cos > 0.9995 {
+            (1.0 - s, s)
+        } else {
+            let angle = cos.acos();
+            (((1.0 - s) * angle).sin() / angle.sin(), (s * angle).sin() / angle.sin())
+        };
+        Quat::new(ka * a.w + kb * b.w, ka * a.v + kb * b.v).normalized()
+    }
+}
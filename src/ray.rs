@@ -1,13 +1,27 @@
 use crate::vec3::{Vec3, Point3};
 
+#[derive(Copy, Clone)]
 pub struct Ray {
     orig: Point3,
-    dir : Vec3
+    dir : Vec3,
+    time: f64,  // when the ray was shot, moving objects are hit where they are at that time
+    wavelength: Option<f64> // in nm, the hero wavelength in spectral mode
 }
 
 impl Ray {
     pub fn new(orig: Point3, dir: Vec3) -> Ray {
-        Ray{ orig, dir } 
+        Ray{ orig, dir, time: 0.0, wavelength: None } 
+    }
+    pub fn with_time(orig: Point3, dir: Vec3, time: f64) -> Ray {
+        Ray{ orig, dir, time, wavelength: None }
+    }
+    pub fn with_wavelength(mut self, wavelength: Option<f64>) -> Ray {
+        self.wavelength = wavelength;
+        self
+    }
+    // the ray going on from a hit at origin, at the same time and wavelength
+    pub fn scattered(&self, origin: Point3, direction: Vec3) -> Ray {
+        Ray{ orig: origin, dir: direction, time: self.time, wavelength: self.wavelength }
     }
     
     pub fn origin(&self) -> Point3 {
@@ -16,6 +30,12 @@ impl Ray {
     pub fn direction(&self) -> Vec3 {
         Vec3::new(self.dir.x(), self.dir.y(), self.dir.z()) 
     }
+    pub fn time(&self) -> f64 {
+        self.time
+    }
+    pub fn wavelength(&self) -> Option<f64> {
+        self.wavelength
+    }
 
     // pass in an argument t can determine a point on this line, starting
     // from the origin
@@ -0,0 +1,173 @@
+// Real roots of low order polynomials, used by the quadric and torus primitives.
+// Coefficients are given from the highest power down, every function
+// returns the real roots in ascending order.
+
+// EPSILON is relative: a term counts as zero when it's this small compared
+// to the size of the roots, so scaling the scene (or the units of x) doesn't
+// change which branch a solver takes
+const EPSILON: f64 = 1e-12;
+
+// the typical size of the roots of x^n + b*x^(n-1) + c*x^(n-2) + ..., from
+// the coefficients given after the leading one. every term of a polynomial
+// in x scales like size^n, so comparing against powers of this is unit free
+fn root_size(coefficients: &[f64]) -> f64 {
+    coefficients.iter().enumerate()
+                .map(|(k, c)| c.abs().powf(1.0 / (k + 1) as f64))
+                .fold(0.0, f64::max)
+}
+
+// a*x^2 + b*x + c = 0
+// uses the numerically stable form, so a root near zero doesn't lose
+// its precision to cancellation; degrades to the linear case when a == 0.
+// a tiny a is fine as it is, it only sends the other root far away
+pub fn solve_quadratic(a: f64, b: f64, c: f64) -> Vec<f64> {
+    if a == 0.0 {
+        if b == 0.0 { return vec![]; }
+        return vec![-c / b];
+    }
+    let discriminant = b*b - 4.0*a*c;
+    if discriminant < 0.0 { return vec![]; }
+
+    let q = -0.5 * (b + b.signum() * discriminant.sqrt());
+    let (mut x1, mut x2) = (q / a, if q != 0.0 { c / q } else { 0.0 });
+    if x1 > x2 { std::mem::swap(&mut x1, &mut x2); }
+    vec![x1, x2]
+}
+
+// a*x^3 + b*x^2 + c*x + d = 0
+pub fn solve_cubic(a: f64, b: f64, c: f64, d: f64) -> Vec<f64> {
+    if a == 0.0 { return solve_quadratic(b, c, d); }
+
+    // substitute x = y - b/3a to get the depressed cubic y^3 + p*y + q = 0
+    let (b, c, d) = (b / a, c / a, d / a);
+    let shift = b / 3.0;
+    let p = c - b*b / 3.0;
+    let q = 2.0*b*b*b / 27.0 - b*c / 3.0 + d;
+
+    let discriminant = (q / 2.0).powi(2) + (p / 3.0).powi(3);
+    let mut roots = if discriminant > 0.0 {
+        // one real root, cardano's formula
+        let s = discriminant.sqrt();
+        vec![(-q / 2.0 + s).cbrt() + (-q / 2.0 - s).cbrt()]
+    } else if p.abs() <= EPSILON * root_size(&[b, c, d]).powi(2) {
+        vec![0.0]
+    } else {
+        // three real roots, trigonometric method
+        let r = 2.0 * (-p / 3.0).sqrt();
+        let phi = (3.0 * q / (p * r)).clamp(-1.0, 1.0).acos() / 3.0;
+        (0..3).map(|k| r * (phi - 2.0 * std::f64::consts::PI * k as f64 / 3.0).cos()).collect()
+    };
+
+    for root in roots.iter_mut() { *root -= shift; }
+    roots.sort_by(f64::total_cmp);
+    roots
+}
+
+// a*x^4 + b*x^3 + c*x^2 + d*x + e = 0
+// ferrari's method, the roots are polished with a few newton steps
+// afterwards since the resolvent cubic loses precision for grazing rays
+pub fn solve_quartic(a: f64, b: f64, c: f64, d: f64, e: f64) -> Vec<f64> {
+    if a == 0.0 { return solve_cubic(b, c, d, e); }
+
+    // substitute x = y - b/4a to get y^4 + p*y^2 + q*y + r = 0
+    let (b, c, d, e) = (b / a, c / a, d / a, e / a);
+    let shift = b / 4.0;
+    let p = c - 3.0*b*b / 8.0;
+    let q = b*b*b / 8.0 - b*c / 2.0 + d;
+    let r = -3.0*b*b*b*b / 256.0 + b*b*c / 16.0 - b*d / 4.0 + e;
+    let size = root_size(&[b, c, d, e]);
+
+    let mut roots = vec![];
+    if q.abs() <= EPSILON * size.powi(3) {
+        // biquadratic, solve for y^2
+        for z in solve_quadratic(1.0, p, r) {
+            if z >= 0.0 { roots.push(z.sqrt()); roots.push(-z.sqrt()); }
+        }
+    } else {
+        // the resolvent cubic always has a positive root since q != 0
+        let m = solve_cubic(1.0, p, p*p / 4.0 - r, -q*q / 8.0)
+                .into_iter().fold(f64::NEG_INFINITY, f64::max);
+        if m <= 0.0 { return vec![]; }
+        let s = (2.0 * m).sqrt();
+        roots.extend(solve_quadratic(1.0,  s, p / 2.0 + m - q / (2.0 * s)));
+        roots.extend(solve_quadratic(1.0, -s, p / 2.0 + m + q / (2.0 * s)));
+    }
+
+    let f  = |x: f64| (((x + b) * x + c) * x + d) * x + e;
+    let df = |x: f64| ((4.0 * x + 3.0 * b) * x + 2.0 * c) * x + d;
+    for root in roots.iter_mut() {
+        *root -= shift;
+        for _ in 0..2 {
+            let slope = df(*root);
+            if slope.abs() <= EPSILON * size.powi(3) { break; }
+            *root -= f(*root) / slope;
+        }
+    }
+    roots.sort_by(f64::total_cmp);
+    roots
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_roots(roots: Vec<f64>, expected: &[f64], tolerance: f64) {
+        assert_eq!(roots.len(), expected.len(), "roots {:?}, expected {:?}", roots, expected);
+        for (root, expected) in roots.iter().zip(expected) {
+            assert!((root - expected).abs() <= tolerance, "roots {:?}, expected {:?}", roots, expected);
+        }
+    }
+
+    #[test]
+    fn double_roots_are_found() {
+        // (x - 3)^2
+        assert_roots(solve_quadratic(1.0, -6.0, 9.0), &[3.0, 3.0], 1e-12);
+        // (x - 1)^2 (x + 2)
+        assert_roots(solve_cubic(1.0, 0.0, -3.0, 2.0), &[-2.0, 1.0, 1.0], 1e-6);
+        // (x - 1)^2 (x + 2)^2, the two double roots
+        let roots = solve_quartic(1.0, 2.0, -3.0, -4.0, 4.0);
+        assert!(!roots.is_empty());
+        for root in roots {
+            assert!((root - 1.0).abs() < 1e-6 || (root + 2.0).abs() < 1e-6, "stray root {}", root);
+        }
+    }
+
+    #[test]
+    fn near_tangent_rays_keep_both_roots() {
+        // a unit sphere grazed at a distance of 1 - 1e-8 from its center
+        let h: f64 = 1.0 - 1e-8;
+        let roots = solve_quadratic(1.0, 0.0, h*h - 1.0);
+        let half = (1.0 - h*h).sqrt();
+        assert_roots(roots, &[-half, half], 1e-12);
+        // and just past it there's nothing
+        let h: f64 = 1.0 + 1e-8;
+        assert!(solve_quadratic(1.0, 0.0, h*h - 1.0).is_empty());
+    }
+
+    #[test]
+    fn distinct_roots_are_sorted() {
+        // (x + 1)(x - 2)(x - 5)
+        assert_roots(solve_cubic(1.0, -6.0, 3.0, 10.0), &[-1.0, 2.0, 5.0], 1e-9);
+        // (x + 3)(x + 1)(x - 2)(x - 4)
+        assert_roots(solve_quartic(1.0, -2.0, -13.0, 14.0, 24.0), &[-3.0, -1.0, 2.0, 4.0], 1e-9);
+    }
+
+    #[test]
+    fn the_epsilon_follows_the_scale_of_the_coefficients() {
+        // a tiny leading coefficient isn't zero when the others are tiny too
+        assert_roots(solve_quadratic(1e-14, 0.0, -1e-14), &[-1.0, 1.0], 1e-12);
+        assert_roots(solve_cubic(1e-14, -6e-14, 3e-14, 10e-14), &[-1.0, 2.0, 5.0], 1e-9);
+        // a quartic in millimeters has the same roots, scaled, as one in meters
+        let k: f64 = 1e3;
+        let scaled = solve_quartic(1.0, -2.0*k, -13.0*k*k, 14.0*k*k*k, 24.0*k*k*k*k);
+        assert_roots(scaled, &[-3e3, -1e3, 2e3, 4e3], 1e-6);
+        assert_roots(solve_quadratic(1.0, -1.2e7, 2e13), &[2e6, 1e7], 1e-6);
+        // a nearly vanishing leading term keeps the near root exact and
+        // sends the other one far away
+        let roots = solve_quadratic(1e-20, 2.0, -4.0);
+        assert_eq!(roots.len(), 2);
+        assert!(roots[0] < -1e19);
+        assert!((roots[1] - 2.0).abs() <= 1e-12);
+        assert_roots(solve_quadratic(0.0, 2.0, -4.0), &[2.0], 0.0);
+    }
+}
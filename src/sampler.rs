@@ -0,0 +1,381 @@
+// Where the samples of a pixel go: every random decision made for one camera
+// sample (position in the pixel, shutter time, lens position, directions
+// bounced into at each hit) takes its own dimension, and a sampler spreads
+// the samples of a pixel well over each of them.
+//
+// Samplers are stateless, a sample is looked up by pixel, sample index and
+// dimension, so every thread can share one and the same seed always gives
+// the same image. SampleStream hands out the dimensions of one camera
+// sample in order.
+
+const ONE_MINUS_EPSILON: f64 = 1.0 - f64::EPSILON / 2.0;
+const PRIMES: [u64; 32] = [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53,
+                           59, 61, 67, 71, 73, 79, 83, 89, 97, 101, 103, 107, 109, 113, 127, 131];
+
+pub trait Sampler: Send + Sync {
+    fn samples_per_pixel(&self) -> u32;
+    // both in [0, 1)
+    fn get_1d(&self, pixel: (u32, u32), index: u32, dimension: u32) -> f64;
+    fn get_2d(&self, pixel: (u32, u32), index: u32, dimension: u32) -> (f64, f64);
+}
+
+// the dimensions of one camera sample, in the order they're asked for
+pub struct SampleStream<'a> {
+    sampler: &'a dyn Sampler,
+    pixel: (u32, u32),
+    index: u32,
+    dimension: u32
+}
+
+impl<'a> SampleStream<'a> {
+    pub fn new(sampler: &'a dyn Sampler, pixel: (u32, u32), index: u32) -> SampleStream<'a> {
+        SampleStream{ sampler, pixel, index, dimension: 0 }
+    }
+
+    pub fn get_1d(&mut self) -> f64 {
+        let value = self.sampler.get_1d(self.pixel, self.index, self.dimension);
+        self.dimension += 1;
+        value
+    }
+    pub fn get_2d(&mut self) -> (f64, f64) {
+        let value = self.sampler.get_2d(self.pixel, self.index, self.dimension);
+        self.dimension += 2;
+        value
+    }
+}
+
+// plain random numbers, no spreading at all
+pub struct Independent {
+    samples: u32,
+    seed: u64
+}
+
+impl Independent {
+    pub fn new(samples: u32, seed: u64) -> Independent {
+        assert!(samples > 0, "independent sampler needs at least one sample!");
+        Independent{ samples, seed }
+    }
+}
+
+impl Sampler for Independent {
+    fn samples_per_pixel(&self) -> u32 {
+        self.samples
+    }
+    fn get_1d(&self, pixel: (u32, u32), index: u32, dimension: u32) -> f64 {
+        to_unit(hash(&[self.seed, pixel.0 as u64, pixel.1 as u64, index as u64, dimension as u64]))
+    }
+    fn get_2d(&self, pixel: (u32, u32), index: u32, dimension: u32) -> (f64, f64) {
+        (self.get_1d(pixel, index, dimension), self.get_1d(pixel, index, dimension + 1))
+    }
+}
+
+// jittered: each dimension is cut into one stratum per sample (a grid of
+// x_strata * y_strata for 2D), every sample lands at a random spot of its own
+// stratum. Samples go through the strata in a different order per dimension
+// so that the dimensions don't correlate.
+pub struct Stratified {
+    x_strata: u32,
+    y_strata: u32,
+    seed: u64
+}
+
+impl Stratified {
+    pub fn new(x_strata: u32, y_strata: u32, seed: u64) -> Stratified {
+        assert!(x_strata > 0 && y_strata > 0, "stratified sampler needs at least one stratum!");
+        Stratified{ x_strata, y_strata, seed }
+    }
+}
+
+impl Sampler for Stratified {
+    fn samples_per_pixel(&self) -> u32 {
+        self.x_strata * self.y_strata
+    }
+    fn get_1d(&self, pixel: (u32, u32), index: u32, dimension: u32) -> f64 {
+        let hash = hash(&[self.seed, pixel.0 as u64, pixel.1 as u64, dimension as u64]);
+        let samples = self.samples_per_pixel();
+        let stratum = permutation_element(index % samples, samples, hash as u32);
+        (stratum as f64 + to_unit(mix_bits(hash ^ index as u64))) / samples as f64
+    }
+    fn get_2d(&self, pixel: (u32, u32), index: u32, dimension: u32) -> (f64, f64) {
+        let hash = hash(&[self.seed, pixel.0 as u64, pixel.1 as u64, dimension as u64]);
+        let samples = self.samples_per_pixel();
+        let stratum = permutation_element(index % samples, samples, hash as u32);
+        let (x, y) = (stratum % self.x_strata, stratum / self.x_strata);
+        let jitter = mix_bits(hash ^ index as u64);
+        ((x as f64 + to_unit(jitter)) / self.x_strata as f64,
+         (y as f64 + to_unit(mix_bits(jitter))) / self.y_strata as f64)
+    }
+}
+
+// the halton sequence, dimension d is the radical inverse in the d-th prime.
+// Digits are owen scrambled per pixel and dimension, which keeps the
+// stratification but stops neighbouring pixels from repeating the same pattern
+pub struct Halton {
+    samples: u32,
+    seed: u64
+}
+
+impl Halton {
+    pub fn new(samples: u32, seed: u64) -> Halton {
+        assert!(samples > 0, "halton sampler needs at least one sample!");
+        Halton{ samples, seed }
+    }
+
+    fn sample(&self, pixel: (u32, u32), index: u32, dimension: u32) -> f64 {
+        // past the table the bases repeat, with their own scrambling
+        let base = PRIMES[dimension as usize % PRIMES.len()];
+        let seed = hash(&[self.seed, pixel.0 as u64, pixel.1 as u64, dimension as u64]);
+        owen_radical_inverse(index as u64, base, seed)
+    }
+}
+
+impl Sampler for Halton {
+    fn samples_per_pixel(&self) -> u32 {
+        self.samples
+    }
+    fn get_1d(&self, pixel: (u32, u32), index: u32, dimension: u32) -> f64 {
+        self.sample(pixel, index, dimension)
+    }
+    fn get_2d(&self, pixel: (u32, u32), index: u32, dimension: u32) -> (f64, f64) {
+        (self.sample(pixel, index, dimension), self.sample(pixel, index, dimension + 1))
+    }
+}
+
+// owen scrambled sobol points, best with a power of two samples. Only the
+// first two sobol dimensions are used: each pair of dimensions gets them
+// with its own scrambling and its own order of the samples ("padding"),
+// which keeps every pair well stratified without big direction number tables
+pub struct Sobol {
+    samples: u32,
+    seed: u64
+}
+
+impl Sobol {
+    pub fn new(samples: u32, seed: u64) -> Sobol {
+        assert!(samples > 0, "sobol sampler needs at least one sample!");
+        Sobol{ samples, seed }
+    }
+
+    fn shuffled_index(&self, pixel: (u32, u32), index: u32, dimension: u32) -> (u32, u64) {
+        let hash = hash(&[self.seed, pixel.0 as u64, pixel.1 as u64, dimension as u64]);
+        (permutation_element(index % self.samples, self.samples, hash as u32), hash)
+    }
+}
+
+impl Sampler for Sobol {
+    fn samples_per_pixel(&self) -> u32 {
+        self.samples
+    }
+    fn get_1d(&self, pixel: (u32, u32), index: u32, dimension: u32) -> f64 {
+        let (index, hash) = self.shuffled_index(pixel, index, dimension);
+        to_unit_u32(owen_scramble(index.reverse_bits(), (hash >> 32) as u32))
+    }
+    fn get_2d(&self, pixel: (u32, u32), index: u32, dimension: u32) -> (f64, f64) {
+        let (index, hash) = self.shuffled_index(pixel, index, dimension);
+        (to_unit_u32(owen_scramble(index.reverse_bits(), (hash >> 32) as u32)),
+         to_unit_u32(owen_scramble(sobol_second(index), hash as u32)))
+    }
+}
+
+// the second sobol dimension, its generator matrix is pascal's triangle mod 2
+fn sobol_second(mut index: u32) -> u32 {
+    let (mut result, mut v) = (0_u32, 1_u32 << 31);
+    while index != 0 {
+        if index & 1 == 1 { result ^= v; }
+        index >>= 1;
+        v ^= v >> 1;
+    }
+    result
+}
+
+// nested uniform scrambling of all 32 bits in one go (Laine and Karras'
+// hash, as improved by Burley): every bit gets flipped depending on the bits above it
+fn owen_scramble(mut v: u32, seed: u32) -> u32 {
+    v = v.reverse_bits();
+    v ^= v.wrapping_mul(0x3d20adea);
+    v = v.wrapping_add(seed);
+    v = v.wrapping_mul((seed >> 16) | 1);
+    v ^= v.wrapping_mul(0x05526c56);
+    v ^= v.wrapping_mul(0x53a22864);
+    v.reverse_bits()
+}
+
+// mirrors the digits of index in base around the decimal point, each digit
+// permuted depending on the digits before it. Keeps going after the
+// index runs out of digits so that the trailing zeros get scrambled too
+fn owen_radical_inverse(mut index: u64, base: u64, seed: u64) -> f64 {
+    let inv_base = 1.0 / base as f64;
+    let (mut inv_base_m, mut reversed) = (1.0, 0_u64);
+    while 1.0 - inv_base_m < 1.0 {
+        let next = index / base;
+        let digit = (index - next * base) as u32;
+        let digit_hash = mix_bits(seed ^ reversed) as u32;
+        reversed = reversed * base + permutation_element(digit, base as u32, digit_hash) as u64;
+        inv_base_m *= inv_base;
+        index = next;
+    }
+    (reversed as f64 * inv_base_m).min(ONE_MINUS_EPSILON)
+}
+
+// element i of a random permutation of 0..length picked by seed, without
+// building it (Kensler's hashing, cycling until the result is in range)
+fn permutation_element(mut i: u32, length: u32, seed: u32) -> u32 {
+    let mut w = length - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    let p = seed;
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < length { break; }
+    }
+    ((i as u64 + p as u64) % length as u64) as u32
+}
+
+pub(crate) fn mix_bits(mut v: u64) -> u64 {
+    v ^= v >> 31;
+    v = v.wrapping_mul(0x7fb5d329728ea185);
+    v ^= v >> 27;
+    v = v.wrapping_mul(0x81dadef4bc2dd44d);
+    v ^= v >> 33;
+    v
+}
+
+// mixes the values into one, the same values always give the same hash
+pub fn hash(values: &[u64]) -> u64 {
+    values.iter().fold(0x9e3779b97f4a7c15, |h, &value| mix_bits(h ^ mix_bits(value)))
+}
+
+// the top 53 bits as a number in [0, 1)
+fn to_unit(bits: u64) -> f64 {
+    (bits >> 11) as f64 / (1_u64 << 53) as f64
+}
+fn to_unit_u32(bits: u32) -> f64 {
+    (bits as f64 / (1_u64 << 32) as f64).min(ONE_MINUS_EPSILON)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PIXEL: (u32, u32) = (7, 3);
+
+    // every cell of an nx x ny grid over [0, 1)^2 holds exactly one of the points
+    fn assert_one_per_cell(points: &[(f64, f64)], nx: u32, ny: u32) {
+        assert_eq!(points.len(), (nx * ny) as usize);
+        let mut cells = vec![0; (nx * ny) as usize];
+        for &(x, y) in points {
+            cells[((y * ny as f64) as u32 * nx + (x * nx as f64) as u32) as usize] += 1;
+        }
+        assert!(cells.iter().all(|&count| count == 1), "{}x{} cells: {:?}", nx, ny, cells);
+    }
+
+    fn points_1d(sampler: &dyn Sampler, count: u32, dimension: u32) -> Vec<(f64, f64)> {
+        (0..count).map(|index| (sampler.get_1d(PIXEL, index, dimension), 0.0)).collect()
+    }
+    fn points_2d(sampler: &dyn Sampler, count: u32, dimension: u32) -> Vec<(f64, f64)> {
+        (0..count).map(|index| sampler.get_2d(PIXEL, index, dimension)).collect()
+    }
+
+    #[test]
+    fn stratified_puts_one_sample_in_each_stratum() {
+        for seed in [0, 1] {
+            let sampler = Stratified::new(4, 3, seed);
+            for dimension in 0..6 {
+                assert_one_per_cell(&points_1d(&sampler, 12, dimension), 12, 1);
+                assert_one_per_cell(&points_2d(&sampler, 12, dimension), 4, 3);
+            }
+        }
+    }
+
+    #[test]
+    fn sobol_fills_every_elementary_interval() {
+        for seed in [0, 1] {
+            for k in 0..=6 {
+                let samples = 1 << k;
+                let sampler = Sobol::new(samples, seed);
+                for dimension in [0, 2, 4, 6] {
+                    assert_one_per_cell(&points_1d(&sampler, samples, dimension), samples, 1);
+                    let points = points_2d(&sampler, samples, dimension);
+                    for a in 0..=k {
+                        assert_one_per_cell(&points, 1 << a, 1 << (k - a));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn halton_fills_every_elementary_interval() {
+        for seed in [0, 1] {
+            let sampler = Halton::new(36, seed);
+            // bases 2, 3, 5 and 7, up to base^most points
+            for (dimension, most) in [(0, 6), (1, 4), (2, 3), (3, 2)] {
+                let base = PRIMES[dimension as usize] as u32;
+                for k in 1..=most {
+                    let count = base.pow(k);
+                    assert_one_per_cell(&points_1d(&sampler, count, dimension), count, 1);
+                }
+            }
+            // bases 2 and 3 together, the first 2^a 3^b points in 2^a x 3^b cells
+            let points = points_2d(&sampler, 36, 0);
+            for (nx, ny) in [(4, 9), (2, 9), (4, 3), (2, 3), (1, 9)] {
+                let cells: Vec<(f64, f64)> = points.iter().take((nx * ny) as usize).copied().collect();
+                assert_one_per_cell(&cells, nx, ny);
+            }
+        }
+    }
+
+    #[test]
+    fn values_are_in_the_unit_interval() {
+        let samplers: [Box<dyn Sampler>; 4] = [Box::new(Independent::new(16, 3)), Box::new(Stratified::new(4, 4, 3)),
+                                               Box::new(Halton::new(16, 3)), Box::new(Sobol::new(16, 3))];
+        for sampler in &samplers {
+            for pixel in [(0, 0), (5, 9), (u32::MAX, 1)] {
+                for index in 0..64 {
+                    for dimension in 0..40 {
+                        let values = [sampler.get_1d(pixel, index, dimension),
+                                      sampler.get_2d(pixel, index, dimension).0,
+                                      sampler.get_2d(pixel, index, dimension).1];
+                        assert!(values.iter().all(|value| (0.0..1.0).contains(value)), "{:?}", values);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn another_seed_scrambles_differently() {
+        let pairs: [(Box<dyn Sampler>, Box<dyn Sampler>); 3] = [
+            (Box::new(Stratified::new(4, 4, 0)), Box::new(Stratified::new(4, 4, 1))),
+            (Box::new(Halton::new(16, 0)), Box::new(Halton::new(16, 1))),
+            (Box::new(Sobol::new(16, 0)), Box::new(Sobol::new(16, 1)))];
+        for (a, b) in &pairs {
+            for dimension in [0, 2] {
+                let (points_a, points_b) = (points_2d(a.as_ref(), 16, dimension), points_2d(b.as_ref(), 16, dimension));
+                assert!(points_a.iter().zip(&points_b).all(|(p, q)| p != q));
+            }
+            // and still one point per interval (base 2 for halton's first dimension)
+            assert_one_per_cell(&points_1d(b.as_ref(), 16, 0), 16, 1);
+        }
+    }
+}
@@ -0,0 +1,284 @@
+// Signed distance fields: shapes described by a function giving the distance
+// from any point to the surface (negative inside). They are rendered by
+// sphere tracing, stepping along the ray by the distance to the surface,
+// which is always safe as nothing can be closer than that.
+// This makes rounded boxes, smooth blends and fractals easy to describe.
+use crate::vec3::{Vec3, Point3};
+use crate::ray::Ray;
+use crate::hittable::{Hittable, HitRecord};
+use crate::interval::Interval;
+use crate::material::Material;
+use crate::aabb::Aabb;
+use crate::sphere::Sphere;
+
+use std::sync::Arc;
+
+const MAX_MARCH_STEPS: u32 = 512;
+const SURFACE_EPSILON: f64 = 1e-4;  // closer than this counts as a hit
+
+pub trait Sdf: Send + Sync {
+    // signed distance from p to the surface, it may underestimate
+    // but must never overestimate the true distance
+    fn distance(&self, p: Point3) -> f64;
+    // a conservative box around the surface, marching happens only inside it
+    fn bounding_box(&self) -> Aabb;
+}
+
+pub struct SdfSphere {
+    center: Point3,
+    radius: f64
+}
+impl SdfSphere {
+    pub fn new(center: Point3, radius: f64) -> SdfSphere {
+        SdfSphere{ center, radius }
+    }
+}
+impl Sdf for SdfSphere {
+    fn distance(&self, p: Point3) -> f64 {
+        (p - self.center).length() - self.radius
+    }
+    fn bounding_box(&self) -> Aabb {
+        let r = Vec3::new(self.radius, self.radius, self.radius);
+        Aabb::new(self.center - r, self.center + r)
+    }
+}
+
+// axis-aligned box whose edges are rounded off by rounding,
+// half_size includes the rounding
+pub struct RoundBox {
+    center: Point3,
+    half_size: Vec3,
+    rounding: f64
+}
+impl RoundBox {
+    pub fn new(center: Point3, half_size: Vec3, rounding: f64) -> RoundBox {
+        RoundBox{ center, half_size, rounding }
+    }
+}
+impl Sdf for RoundBox {
+    fn distance(&self, p: Point3) -> f64 {
+        let p = p - self.center;
+        let r = self.rounding;
+        let q = Vec3::new(p.x().abs() - self.half_size.x() + r,
+                          p.y().abs() - self.half_size.y() + r,
+                          p.z().abs() - self.half_size.z() + r);
+        let outside = Vec3::new(q.x().max(0.0), q.y().max(0.0), q.z().max(0.0)).length();
+        let inside = q.x().max(q.y()).max(q.z()).min(0.0);
+        outside + inside - r
+    }
+    fn bounding_box(&self) -> Aabb {
+        Aabb::new(self.center - self.half_size, self.center + self.half_size)
+    }
+}
+
+// torus in the xz plane, like the analytic Torus
+pub struct SdfTorus {
+    center: Point3,
+    major_radius: f64,
+    minor_radius: f64
+}
+impl SdfTorus {
+    pub fn new(center: Point3, major_radius: f64, minor_radius: f64) -> SdfTorus {
+        SdfTorus{ center, major_radius, minor_radius }
+    }
+}
+impl Sdf for SdfTorus {
+    fn distance(&self, p: Point3) -> f64 {
+        let p = p - self.center;
+        let ring = (p.x().powi(2) + p.z().powi(2)).sqrt() - self.major_radius;
+        (ring.powi(2) + p.y().powi(2)).sqrt() - self.minor_radius
+    }
+    fn bounding_box(&self) -> Aabb {
+        let outer = self.major_radius + self.minor_radius;
+        let extent = Vec3::new(outer, self.minor_radius, outer);
+        Aabb::new(self.center - extent, self.center + extent)
+    }
+}
+
+// the mandelbulb fractal, power 8 gives the classic shape
+pub struct Mandelbulb {
+    center: Point3,
+    scale: f64,
+    power: f64,
+    iterations: u32
+}
+impl Mandelbulb {
+    pub fn new(center: Point3, scale: f64, power: f64, iterations: u32) -> Mandelbulb {
+        Mandelbulb{ center, scale, power, iterations }
+    }
+}
+impl Sdf for Mandelbulb {
+    // distance estimator from the running derivative of the iteration
+    fn distance(&self, p: Point3) -> f64 {
+        let c = (p - self.center) / self.scale;
+        let mut z = c;
+        let mut dr = 1.0;
+        let mut r = 0.0;
+        for _ in 0..self.iterations {
+            r = z.length();
+            if r > 2.0 { break; }
+            // z^power is 0 there, and its direction undefined
+            if r == 0.0 { z = c; dr = 1.0; continue; }
+            // z = z^power + c, in spherical coordinates
+            let theta = (z.z() / r).acos() * self.power;
+            let phi = z.y().atan2(z.x()) * self.power;
+            dr = r.powf(self.power - 1.0) * self.power * dr + 1.0;
+            let zr = r.powf(self.power);
+            z = zr * Vec3::new(theta.sin() * phi.cos(), theta.sin() * phi.sin(), theta.cos()) + c;
+        }
+        if r == 0.0 { return -self.scale; }
+        0.5 * r.ln() * r / dr * self.scale
+    }
+    fn bounding_box(&self) -> Aabb {
+        // once |z| > 2^(1/(power-1)) we have |z^power + c| > |z| for any |c| <= |z|,
+        // so every point further out than that escapes, and nothing past the
+        // escape radius of 2 in distance() counts as inside anyway
+        let radius = if self.power > 1.0 { 2_f64.powf(1.0 / (self.power - 1.0)).min(2.0) } else { 2.0 };
+        let extent = Vec3::new(radius, radius, radius) * (1.01 * self.scale);
+        Aabb::new(self.center - extent, self.center + extent)
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum SdfOp {
+    Union,
+    Intersection,
+    Difference      // left minus right
+}
+
+// combines two fields, with smoothness > 0 the seam between them is
+// blended over about that distance (polynomial smooth min)
+pub struct SdfCombine {
+    op: SdfOp,
+    smoothness: f64,
+    left: Arc<dyn Sdf>,
+    right: Arc<dyn Sdf>
+}
+impl SdfCombine {
+    pub fn new(op: SdfOp, smoothness: f64, left: Arc<dyn Sdf>, right: Arc<dyn Sdf>) -> SdfCombine {
+        SdfCombine{ op, smoothness, left, right }
+    }
+    pub fn union(left: Arc<dyn Sdf>, right: Arc<dyn Sdf>) -> SdfCombine {
+        SdfCombine::new(SdfOp::Union, 0.0, left, right)
+    }
+    pub fn smooth_union(smoothness: f64, left: Arc<dyn Sdf>, right: Arc<dyn Sdf>) -> SdfCombine {
+        SdfCombine::new(SdfOp::Union, smoothness, left, right)
+    }
+    pub fn intersection(left: Arc<dyn Sdf>, right: Arc<dyn Sdf>) -> SdfCombine {
+        SdfCombine::new(SdfOp::Intersection, 0.0, left, right)
+    }
+    pub fn difference(left: Arc<dyn Sdf>, right: Arc<dyn Sdf>) -> SdfCombine {
+        SdfCombine::new(SdfOp::Difference, 0.0, left, right)
+    }
+}
+
+pub fn smooth_min(a: f64, b: f64, k: f64) -> f64 {
+    if k <= 0.0 { return a.min(b); }
+    let h = (k - (a - b).abs()).max(0.0) / k;
+    a.min(b) - h * h * k / 4.0
+}
+pub fn smooth_max(a: f64, b: f64, k: f64) -> f64 {
+    -smooth_min(-a, -b, k)
+}
+
+impl Sdf for SdfCombine {
+    fn distance(&self, p: Point3) -> f64 {
+        let (a, b) = (self.left.distance(p), self.right.distance(p));
+        match self.op {
+            SdfOp::Union        => smooth_min(a, b, self.smoothness),
+            SdfOp::Intersection => smooth_max(a, b, self.smoothness),
+            SdfOp::Difference   => smooth_max(a, -b, self.smoothness)
+        }
+    }
+    fn bounding_box(&self) -> Aabb {
+        let (left, right) = (self.left.bounding_box(), self.right.bounding_box());
+        match self.op {
+            // smooth min pulls the surface out by at most smoothness / 4
+            SdfOp::Union        => left.union(&right).pad(self.smoothness.max(0.0) / 4.0),
+            SdfOp::Intersection => left.intersection(&right),
+            SdfOp::Difference   => left
+        }
+    }
+}
+
+// makes a distance field renderable, like a Sphere it carries the material
+pub struct SdfObject {
+    sdf: Arc<dyn Sdf>,
+    material: Arc<dyn Material>
+}
+
+impl SdfObject {
+    pub fn new(sdf: Arc<dyn Sdf>, material: Arc<dyn Material>) -> SdfObject {
+        SdfObject{ sdf, material }
+    }
+
+    // central differences around p, pointing towards growing distance (outside)
+    fn normal(&self, p: Point3) -> Vec3 {
+        let h = SURFACE_EPSILON / 2.0;
+        let mut gradient = Vec3::new(0.0, 0.0, 0.0);
+        for i in 0..3 {
+            let mut offset = Vec3::new(0.0, 0.0, 0.0);
+            offset[i] = h;
+            gradient[i] = self.sdf.distance(p + offset) - self.sdf.distance(p - offset);
+        }
+        if gradient.near_zero() { return Vec3::new(0.0, 1.0, 0.0); }
+        gradient.unit_vector()
+    }
+}
+
+impl Hittable for SdfObject {
+    fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        let range = self.sdf.bounding_box().pad(SURFACE_EPSILON).clip(ray, ray_t)?;
+        // distances are in world units, t is in units of the direction's length
+        let speed = ray.direction().length();
+        let mut t = range.min();
+
+        // a scattered ray starts right on the surface, slide off it first
+        // so that we don't hit the same point again (starting on the
+        // bounding box instead, being that close is simply a hit)
+        let mut distance = self.sdf.distance(ray.at(t));
+        while range.min() == ray_t.min() && distance.abs() < SURFACE_EPSILON && t < range.max() {
+            t += SURFACE_EPSILON / speed;
+            distance = self.sdf.distance(ray.at(t));
+        }
+        // marching from inside towards the way out works the same with the sign flipped
+        let side = distance.signum();
+
+        for _ in 0..MAX_MARCH_STEPS {
+            if t > range.max() { return None; }
+            let p = ray.at(t);
+            let distance = side * self.sdf.distance(p);
+            if distance < SURFACE_EPSILON {
+                let uv = Sphere::uv((p - self.sdf.bounding_box().center()).unit_vector());
+                return Some(HitRecord::new(ray, t, self.normal(p), uv, self.material.clone()));
+            }
+            t += distance / speed;
+        }
+        None
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.sdf.bounding_box()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mandelbulbs_fit_their_box() {
+        for power in [1.5, 2.0, 3.0, 8.0, 16.0] {
+            let bulb = Mandelbulb::new(Point3::new(1.0, 2.0, 3.0), 2.0, power, 32);
+            let bbox = bulb.bounding_box();
+            // points on the box faces along every axis are all outside
+            for k in 0..3 {
+                for side in [bbox.min(), bbox.max()] {
+                    let mut p = bulb.center;
+                    p[k] = side[k];
+                    assert!(bulb.distance(p) > 0.0, "power {} reaches the box along axis {}", power, k);
+                }
+            }
+        }
+    }
+}
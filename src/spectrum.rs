@@ -0,0 +1,293 @@
+// Spectral rendering: instead of three RGB channels a path carries light at
+// a few wavelengths, so effects that depend on the wavelength (dispersion,
+// colors that match under one light but not another) come out right.
+//
+// Every camera sample picks a hero wavelength at random and three more
+// spread evenly around the visible range from it, which cancels most of the
+// color noise a single wavelength would give. The radiance found at them is
+// weighed by the CIE color matching functions into XYZ, and from there into
+// the working space.
+//
+// The scene's colors are RGB, they're turned into smooth spectra the way
+// Jakob and Hanika (2019) do it: a sigmoid of a quadratic in the wavelength,
+// fitted so that the spectrum lit by D65 gives back the RGB color. Fits are
+// made once per color and cached. Lights (the sky) are such a spectrum
+// times D65, so white light is D65 and a white surface reflects all of it.
+use crate::vec3::Color;
+use crate::colorspace::{ColorSpace, mul};
+
+use std::collections::HashMap;
+use std::ops::{Add, Mul};
+use std::sync::RwLock;
+
+pub const LAMBDA_MIN: f64 = 380.0;    // nm
+pub const LAMBDA_MAX: f64 = 780.0;
+pub const WAVELENGTHS: usize = 4;     // per path
+const INTEGRATION_STEP: f64 = 5.0;    // nm, for fitting
+const FIT_STEPS: u32 = 8;             // from grey towards the color
+const FIT_ITERATIONS: u32 = 20;
+const FIT_TOLERANCE: f64 = 1e-7;
+const MIN_REFLECTANCE: f64 = 1e-3;    // sigmoids never quite reach 0 or 1
+// CIE standard illuminant D65, every 10 nm from LAMBDA_MIN
+const D65: [f64; 41] = [49.9755, 54.6482, 82.7549, 91.486, 93.4318, 86.6823, 104.865, 117.008, 117.812,
+                        114.861, 115.923, 108.811, 109.354, 107.802, 104.79, 107.689, 104.405, 104.046,
+                        100.0, 96.3342, 95.788, 88.6856, 90.0062, 89.5991, 87.6987, 83.2886, 83.6992,
+                        80.0268, 80.2146, 82.2778, 78.2842, 69.7213, 71.6091, 74.349, 61.604, 69.8856,
+                        75.087, 63.5927, 46.4182, 66.8054, 63.3828];
+
+// the wavelengths a path carries and their probability densities. A pdf of
+// 0 means the wavelength was dropped (see terminate_secondary)
+#[derive(Copy, Clone)]
+pub struct SampledWavelengths {
+    lambda: [f64; WAVELENGTHS],
+    pdf: [f64; WAVELENGTHS]
+}
+
+impl SampledWavelengths {
+    // u in [0, 1) places the hero wavelength, the others follow at equal steps
+    pub fn sample(u: f64) -> SampledWavelengths {
+        let range = LAMBDA_MAX - LAMBDA_MIN;
+        let mut lambda = [0.0; WAVELENGTHS];
+        for (i, value) in lambda.iter_mut().enumerate() {
+            let offset = (u + i as f64 / WAVELENGTHS as f64).fract();
+            *value = LAMBDA_MIN + offset * range;
+        }
+        SampledWavelengths{ lambda, pdf: [1.0 / range; WAVELENGTHS] }
+    }
+
+    pub fn lambda(&self, i: usize) -> f64 {
+        self.lambda[i]
+    }
+    pub fn hero(&self) -> f64 {
+        self.lambda[0]
+    }
+
+    // for things that send every wavelength its own way (dispersion):
+    // only the hero goes on, standing in for all of them
+    pub fn terminate_secondary(&mut self) {
+        if self.secondary_terminated() { return; }
+        for pdf in self.pdf[1..].iter_mut() { *pdf = 0.0; }
+        self.pdf[0] /= WAVELENGTHS as f64;
+    }
+    pub fn secondary_terminated(&self) -> bool {
+        self.pdf[1..].iter().all(|&pdf| pdf == 0.0)
+    }
+}
+
+// a value per sampled wavelength
+#[derive(Copy, Clone)]
+pub struct SampledSpectrum([f64; WAVELENGTHS]);
+
+impl SampledSpectrum {
+    pub fn constant(value: f64) -> SampledSpectrum {
+        SampledSpectrum([value; WAVELENGTHS])
+    }
+    pub fn from_fn(f: impl Fn(usize) -> f64) -> SampledSpectrum {
+        SampledSpectrum(std::array::from_fn(f))
+    }
+    pub fn value(&self, i: usize) -> f64 {
+        self.0[i]
+    }
+}
+
+impl Add for SampledSpectrum {
+    type Output = SampledSpectrum;
+    fn add(self, other: SampledSpectrum) -> SampledSpectrum {
+        SampledSpectrum::from_fn(|i| self.0[i] + other.0[i])
+    }
+}
+impl Mul for SampledSpectrum {
+    type Output = SampledSpectrum;
+    fn mul(self, other: SampledSpectrum) -> SampledSpectrum {
+        SampledSpectrum::from_fn(|i| self.0[i] * other.0[i])
+    }
+}
+impl Mul<f64> for SampledSpectrum {
+    type Output = SampledSpectrum;
+    fn mul(self, other: f64) -> SampledSpectrum {
+        SampledSpectrum::from_fn(|i| self.0[i] * other)
+    }
+}
+
+// scale * sigmoid(c0 t^2 + c1 t + c2), t the wavelength mapped to [0, 1]
+#[derive(Copy, Clone)]
+pub struct RgbSpectrum {
+    coefficients: [f64; 3],
+    scale: f64
+}
+
+impl RgbSpectrum {
+    pub fn evaluate(&self, lambda: f64) -> f64 {
+        let t = (lambda - LAMBDA_MIN) / (LAMBDA_MAX - LAMBDA_MIN);
+        let [c0, c1, c2] = self.coefficients;
+        self.scale * sigmoid((c0 * t + c1) * t + c2)
+    }
+    pub fn sample(&self, wavelengths: &SampledWavelengths) -> SampledSpectrum {
+        SampledSpectrum::from_fn(|i| self.evaluate(wavelengths.lambda[i]))
+    }
+}
+
+// RGB colors of the working space as spectra, and back
+pub struct RgbSpectra {
+    from_xyz: [[f64; 3]; 3],
+    white: Color,       // what a spectrum of all ones comes out as, to make that exactly white
+    y_integral: f64,    // of D65 times the luminance matching function
+    cache: RwLock<HashMap<[u64; 3], RgbSpectrum>>
+}
+
+impl RgbSpectra {
+    pub fn new(working_space: ColorSpace) -> RgbSpectra {
+        let mut spectra = RgbSpectra{ from_xyz: working_space.from_xyz(), white: Color::new(1.0, 1.0, 1.0),
+                                      y_integral: 1.0, cache: RwLock::new(HashMap::new()) };
+        spectra.y_integral = integrate(|lambda| d65(lambda) * cie_y(lambda));
+        spectra.white = spectra.reflected_rgb(|_| 1.0);
+        spectra
+    }
+
+    // the spectrum of a reflectance (or, times D65, of a light) with this color
+    pub fn upsample(&self, color: Color) -> RgbSpectrum {
+        let key = [color.x().to_bits(), color.y().to_bits(), color.z().to_bits()];
+        if let Some(spectrum) = self.cache.read().unwrap().get(&key) { return *spectrum; }
+        let spectrum = self.fit(color);
+        self.cache.write().unwrap().insert(key, spectrum);
+        spectrum
+    }
+
+    pub fn reflectance(&self, color: Color, wavelengths: &SampledWavelengths) -> SampledSpectrum {
+        self.upsample(color).sample(wavelengths)
+    }
+    pub fn illuminant(&self, color: Color, wavelengths: &SampledWavelengths) -> SampledSpectrum {
+        self.reflectance(color, wavelengths) * SampledSpectrum::from_fn(|i| d65(wavelengths.lambda[i]))
+    }
+
+    // the color of the radiance a path brought back at its wavelengths
+    pub fn to_rgb(&self, radiance: SampledSpectrum, wavelengths: &SampledWavelengths) -> Color {
+        let mut xyz = Color::new(0.0, 0.0, 0.0);
+        for i in 0..WAVELENGTHS {
+            if wavelengths.pdf[i] == 0.0 { continue; }
+            let lambda = wavelengths.lambda[i];
+            let weight = radiance.0[i] / wavelengths.pdf[i] / WAVELENGTHS as f64;
+            xyz += weight * Color::new(cie_x(lambda), cie_y(lambda), cie_z(lambda));
+        }
+        self.xyz_to_rgb(xyz / self.y_integral)
+    }
+
+    fn xyz_to_rgb(&self, xyz: Color) -> Color {
+        let rgb = mul(&self.from_xyz, xyz);
+        Color::new(rgb.x() / self.white.x(), rgb.y() / self.white.y(), rgb.z() / self.white.z())
+    }
+
+    // the color of a surface with this reflectance spectrum under D65
+    fn reflected_rgb(&self, reflectance: impl Fn(f64) -> f64) -> Color {
+        let xyz = Color::new(integrate(|lambda| reflectance(lambda) * d65(lambda) * cie_x(lambda)),
+                             integrate(|lambda| reflectance(lambda) * d65(lambda) * cie_y(lambda)),
+                             integrate(|lambda| reflectance(lambda) * d65(lambda) * cie_z(lambda)));
+        self.xyz_to_rgb(xyz / self.y_integral)
+    }
+
+    // gauss-newton on the three coefficients. Saturated colors need large
+    // coefficients that newton can't find from far away, so we start at the
+    // grey of the same brightness (which is exact) and walk over to the color
+    fn fit(&self, color: Color) -> RgbSpectrum {
+        // greys are flat, sigmoid(0) is a half
+        if color.x() == color.y() && color.y() == color.z() {
+            return RgbSpectrum{ coefficients: [0.0; 3], scale: 2.0 * color.x() };
+        }
+        let scale = color.x().max(color.y()).max(color.z()).max(1.0);
+        let mut target = color / scale;
+        for k in 0..3 { target[k] = target[k].clamp(MIN_REFLECTANCE, 1.0 - MIN_REFLECTANCE); }
+        let grey = (target.x() + target.y() + target.z()) / 3.0;
+        let mut coefficients = [0.0, 0.0, inverse_sigmoid(grey)];
+
+        let residual = |c: [f64; 3], goal: Color| {
+            let spectrum = RgbSpectrum{ coefficients: c, scale: 1.0 };
+            self.reflected_rgb(|lambda| spectrum.evaluate(lambda)) - goal
+        };
+        for step in 1..=FIT_STEPS {
+            let goal = Color::new(grey, grey, grey) + (step as f64 / FIT_STEPS as f64) * (target - Color::new(grey, grey, grey));
+            for _ in 0..FIT_ITERATIONS {
+                let r = residual(coefficients, goal);
+                if r.length_squared() < FIT_TOLERANCE * FIT_TOLERANCE { break; }
+                // jacobian by finite differences, a column per coefficient
+                let mut jacobian = [[0.0; 3]; 3];
+                for j in 0..3 {
+                    let mut shifted = coefficients;
+                    shifted[j] += 1e-5;
+                    let dr = (residual(shifted, goal) - r) / 1e-5;
+                    for i in 0..3 { jacobian[i][j] = dr[i]; }
+                }
+                let Some(delta) = solve(jacobian, r) else { break; };
+                // halve the step until it gets closer, colors no reflectance
+                // can reach (outside sRGB in wider spaces) end up as near as they get
+                let mut step_size = 1.0;
+                while step_size > 1e-4 {
+                    let tried: [f64; 3] = std::array::from_fn(|j| coefficients[j] - step_size * delta[j]);
+                    if residual(tried, goal).length_squared() < r.length_squared() {
+                        coefficients = tried;
+                        break;
+                    }
+                    step_size *= 0.5;
+                }
+                if step_size <= 1e-4 { break; }
+            }
+        }
+        RgbSpectrum{ coefficients, scale }
+    }
+}
+
+fn sigmoid(x: f64) -> f64 {
+    if x.is_infinite() { return if x > 0.0 { 1.0 } else { 0.0 }; }
+    0.5 + x / (2.0 * (1.0 + x * x).sqrt())
+}
+fn inverse_sigmoid(y: f64) -> f64 {
+    let x = 2.0 * y - 1.0;
+    x / (1.0 - x * x).sqrt()
+}
+
+// a x = b by cramer's rule, None if a is singular
+fn solve(a: [[f64; 3]; 3], b: Color) -> Option<[f64; 3]> {
+    let det = |m: [[f64; 3]; 3]| m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+                               - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+                               + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0]);
+    let d = det(a);
+    if d.abs() < 1e-300 { return None; }
+    let mut x = [0.0; 3];
+    for (j, value) in x.iter_mut().enumerate() {
+        let mut m = a;
+        for i in 0..3 { m[i][j] = b[i]; }
+        *value = det(m) / d;
+    }
+    Some(x)
+}
+
+// trapezoids over the visible range
+fn integrate(f: impl Fn(f64) -> f64) -> f64 {
+    let steps = ((LAMBDA_MAX - LAMBDA_MIN) / INTEGRATION_STEP) as u32;
+    (0..=steps).map(|i| {
+        let weight = if i == 0 || i == steps { 0.5 } else { 1.0 };
+        weight * f(LAMBDA_MIN + i as f64 * INTEGRATION_STEP)
+    }).sum::<f64>() * INTEGRATION_STEP
+}
+
+// linearly interpolated from the table
+pub fn d65(lambda: f64) -> f64 {
+    let x = ((lambda - LAMBDA_MIN) / 10.0).clamp(0.0, (D65.len() - 1) as f64);
+    let i = (x as usize).min(D65.len() - 2);
+    D65[i] + (x - i as f64) * (D65[i + 1] - D65[i])
+}
+
+// the CIE 1931 color matching functions, as the sums of piecewise gaussians
+// fitted by Wyman, Sloan and Shirley (2013)
+fn lobe(lambda: f64, mean: f64, sigma_below: f64, sigma_above: f64) -> f64 {
+    let t = (lambda - mean) / if lambda < mean { sigma_below } else { sigma_above };
+    (-0.5 * t * t).exp()
+}
+pub fn cie_x(lambda: f64) -> f64 {
+    1.056 * lobe(lambda, 599.8, 37.9, 31.0) + 0.362 * lobe(lambda, 442.0, 16.0, 26.7) - 0.065 * lobe(lambda, 501.1, 20.4, 26.2)
+}
+pub fn cie_y(lambda: f64) -> f64 {
+    0.821 * lobe(lambda, 568.8, 46.9, 40.5) + 0.286 * lobe(lambda, 530.9, 16.3, 31.1)
+}
+pub fn cie_z(lambda: f64) -> f64 {
+    1.217 * lobe(lambda, 437.0, 11.8, 36.0) + 0.681 * lobe(lambda, 459.0, 26.0, 13.8)
+}
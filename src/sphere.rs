@@ -1,29 +1,49 @@
-use crate::vec3::{Point3};
+use crate::vec3::{Vec3, Point3};
 use crate::ray::Ray;
 use crate::hittable::{Hittable, HitRecord};
 use crate::interval::Interval;
 use crate::material::Material;
+use crate::aabb::Aabb;
 
 use std::sync::Arc;
+use std::f64::consts::PI;
 
 pub struct Sphere {
-    center: Point3,
+    center: Point3,     // center at time 0
+    velocity: Vec3,     // how far the center moves until time 1
     radius: f64,
     material: Arc<dyn Material>
 }
 
 impl Sphere {
     pub fn new(center: Point3, radius: f64, material: Arc<dyn Material>) -> Sphere {
-        Sphere{ center, radius, material }
+        Sphere{ center, velocity: Vec3::new(0.0, 0.0, 0.0), radius, material }
+    }
+    // a sphere moving linearly from center0 at time 0 to center1 at time 1
+    pub fn moving(center0: Point3, center1: Point3, radius: f64, material: Arc<dyn Material>) -> Sphere {
+        Sphere{ center: center0, velocity: center1 - center0, radius, material }
+    }
+
+    fn center_at(&self, time: f64) -> Point3 {
+        self.center + time * self.velocity
+    }
+
+    // p is a point on the unit sphere, u is the angle around the y axis
+    // starting from x = -1, v is the angle from y = -1 up to y = +1
+    pub(crate) fn uv(p: Point3) -> (f64, f64) {
+        let theta = (-p.y()).clamp(-1.0, 1.0).acos();
+        let phi = (-p.z()).atan2(p.x()) + PI;
+        (phi / (2.0*PI), theta / PI)
     }
 }
 
 impl Hittable for Sphere {
     fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        let center = self.center_at(ray.time());
         // a vector dot product with itself == vector.length_squared()
         let a = ray.direction().length_squared();
-        let b = 2.0 * (ray.origin() - self.center).dot(&ray.direction());
-        let c = (ray.origin() - self.center).length_squared()
+        let b = 2.0 * (ray.origin() - center).dot(&ray.direction());
+        let c = (ray.origin() - center).length_squared()
                 - self.radius.powi(2);
 
         let discriminant = b.powi(2) - 4.0 * a * c;
@@ -44,19 +64,16 @@ impl Hittable for Sphere {
         // NOTE: you can use vector.unit_vector() to get unit normal,
         // but divide by radius is a trick for generating hollow glass sphere.
         let intersection = ray.at(root);
-        let outside_normal = (intersection - self.center) / self.radius;
-        if outside_normal.dot(&ray.direction()) <= 0.0 {
-            Some(HitRecord{ p: intersection, 
-                            normal: outside_normal, 
-                            t: root, 
-                            from_outside: true,
-                            material: self.material.clone() })
-        } else {
-            Some(HitRecord{ p: intersection, 
-                            normal: -outside_normal, 
-                            t: root, 
-                            from_outside: false,
-                            material: self.material.clone() })
-        }
+        let outside_normal = (intersection - center) / self.radius;
+        Some(HitRecord::new(ray, root, outside_normal,
+                            Self::uv((intersection - center) / self.radius.abs()),
+                            self.material.clone()))
+    }
+
+    // covers the whole way from time 0 to time 1
+    fn bounding_box(&self) -> Aabb {
+        let r = Vec3::new(self.radius.abs(), self.radius.abs(), self.radius.abs());
+        let (start, end) = (self.center_at(0.0), self.center_at(1.0));
+        Aabb::new(start - r, start + r).union(&Aabb::new(end - r, end + r))
     }
 }
@@ -0,0 +1,130 @@
+// Stereo rendering for VR previews: the image shows the scene from two eyes
+// interocular apart, either as one eye only or both stacked in one image.
+// It wraps another projection and moves each of its rays to the eye's position.
+use crate::projection::{Projection, View, CameraRay, Equirectangular};
+
+use std::sync::Arc;
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum StereoLayout {
+    Left,           // the whole image is the left eye
+    Right,
+    TopBottom,      // left eye on top, right eye below
+    SideBySide      // left eye on the left, right eye on the right
+}
+
+impl StereoLayout {
+    // the aspect ratio of the whole image for eyes of the given aspect ratio
+    pub fn image_aspect_ratio(&self, eye_aspect_ratio: f64) -> f64 {
+        match self {
+            StereoLayout::TopBottom  => eye_aspect_ratio / 2.0,
+            StereoLayout::SideBySide => eye_aspect_ratio * 2.0,
+            _ => eye_aspect_ratio
+        }
+    }
+}
+
+pub struct Stereo {
+    inner: Arc<dyn Projection>,
+    interocular: f64,   // distance between the eyes
+    convergence: f64,   // distance where both eyes see the same point, INFINITY for parallel eyes
+    layout: StereoLayout,
+    omnidirectional: bool
+}
+
+impl Stereo {
+    // both eyes sit to the sides of lookfrom along the camera's u vector
+    // and converge on a plane convergence in front of the camera
+    pub fn new(inner: Arc<dyn Projection>, interocular: f64, convergence: f64, layout: StereoLayout) -> Stereo {
+        Stereo{ inner, interocular, convergence, layout, omnidirectional: false }
+    }
+
+    // omni-directional stereo 360 panorama: for every direction the eyes
+    // are placed perpendicular to it, as if the viewer turned their head
+    // to look there. Towards the poles the eyes move together, so looking
+    // straight up or down doesn't swirl. Each eye is a 2:1 equirectangular
+    // image, render it at layout.image_aspect_ratio(2.0) (a square for top/bottom)
+    pub fn omnidirectional(interocular: f64, convergence: f64, layout: StereoLayout) -> Stereo {
+        Stereo{ inner: Arc::new(Equirectangular), interocular, convergence, layout, omnidirectional: true }
+    }
+
+    // which eye (-1 left, +1 right) and where on its own image (x, y) is
+    fn split(&self, x: f64, y: f64) -> (f64, f64, f64) {
+        match self.layout {
+            StereoLayout::Left  => (-1.0, x, y),
+            StereoLayout::Right => ( 1.0, x, y),
+            StereoLayout::TopBottom => if y < 0.5 { (-1.0, x, 2.0 * y) } else { (1.0, x, 2.0 * y - 1.0) },
+            StereoLayout::SideBySide => if x < 0.5 { (-1.0, 2.0 * x, y) } else { (1.0, 2.0 * x - 1.0, y) }
+        }
+    }
+}
+
+impl Projection for Stereo {
+    fn generate_ray(&self, view: &View, x: f64, y: f64, lens: (f64, f64)) -> Option<CameraRay> {
+        let (side, x, y) = self.split(x, y);
+        let mut eye_view = *view;
+        eye_view.aspect_ratio = match self.layout {
+            StereoLayout::TopBottom  => view.aspect_ratio * 2.0,
+            StereoLayout::SideBySide => view.aspect_ratio / 2.0,
+            _ => view.aspect_ratio
+        };
+        let mut ray = self.inner.generate_ray(&eye_view, x, y, lens)?;
+        let (origin, direction) = (ray.origin, ray.direction);
+
+        // sideways direction the eye is moved along
+        let offset = if self.omnidirectional {
+            // |direction x v| is |direction| cos(latitude), the eyes come
+            // together at the poles
+            direction.cross(&view.v) / direction.length()
+        } else {
+            view.u
+        };
+        let eye = origin + side * self.interocular / 2.0 * offset;
+        ray.origin = eye;
+        if self.convergence.is_infinite() { return Some(ray); }
+
+        // aim at the point the center ray sees at the convergence distance,
+        // a plane for flat images and a sphere for panoramas
+        let target = if self.omnidirectional {
+            origin + self.convergence * direction.unit_vector()
+        } else {
+            let forward = -direction.dot(&view.w);
+            if forward <= 0.0 { return Some(ray); }
+            origin + (self.convergence / forward) * direction
+        };
+        ray.direction = target - eye;
+        Some(ray)
+    }
+
+    fn has_lens(&self) -> bool {
+        self.inner.has_lens()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec3::{Vec3, Point3};
+
+    fn view() -> View {
+        View{ origin: Point3::new(0.0, 0.0, 0.0), u: Vec3::new(1.0, 0.0, 0.0), v: Vec3::new(0.0, 1.0, 0.0),
+              w: Vec3::new(0.0, 0.0, 1.0), theta: 1.0, focus_dist: 1.0, aspect_ratio: 1.0 }
+    }
+
+    #[test]
+    fn omnidirectional_eyes_meet_at_the_poles() {
+        let stereo = Stereo::omnidirectional(0.4, f64::INFINITY, StereoLayout::TopBottom);
+        // the left eye is the top half, its latitude is (0.5 - 2y) pi
+        let eye_distance = |y: f64| stereo.generate_ray(&view(), 0.5, y, (0.0, 0.0)).unwrap().origin.length();
+        assert!((eye_distance(0.25) - 0.2).abs() < 1e-12);         // the horizon
+        assert!((eye_distance(1.0 / 12.0) - 0.1).abs() < 1e-12);   // 60 degrees up
+        assert!(eye_distance(0.0) < 1e-12);                        // straight up
+    }
+
+    #[test]
+    fn layouts_give_each_eye_its_aspect_ratio() {
+        assert_eq!(StereoLayout::TopBottom.image_aspect_ratio(2.0), 1.0);
+        assert_eq!(StereoLayout::SideBySide.image_aspect_ratio(2.0), 4.0);
+        assert_eq!(StereoLayout::Left.image_aspect_ratio(2.0), 2.0);
+    }
+}
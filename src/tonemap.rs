@@ -0,0 +1,167 @@
+// The display transform: how the film's linear radiance becomes 8-bit
+// pixels. Exposure scales the light in stops, a tone mapper squeezes the
+// highlights into what a screen can show instead of clipping them, the sRGB
+// curve encodes the result for the screen and a little noise (dithering)
+// before rounding to 8 bits keeps smooth gradients from banding.
+// Display P3 screens use the same curve as sRGB ones, only the primaries
+// differ (see colorspace.rs).
+use crate::vec3::Color;
+use crate::colorspace::{ColorSpace, Mat3, mul};
+use crate::sampler::hash;
+
+const MAX_COLOR: f64 = 255.0;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ToneMapper {
+    Clip,       // nothing, everything above 1 is white
+    Reinhard,   // x / (1 + x) on the luminance, keeps hues
+    Aces,       // Stephen Hill's fit of the ACES reference rendering
+    Agx         // the "minimal" AgX: a log encoding and a sigmoid that desaturates highlights
+}
+
+impl ToneMapper {
+    pub fn from_name(name: &str) -> Option<ToneMapper> {
+        match name {
+            "clip"     => Some(ToneMapper::Clip),
+            "reinhard" => Some(ToneMapper::Reinhard),
+            "aces"     => Some(ToneMapper::Aces),
+            "agx"      => Some(ToneMapper::Agx),
+            _ => None
+        }
+    }
+
+    // AgX's sigmoid is made to give display values, already encoded with
+    // the sRGB curve, the others give linear values
+    pub fn display_encoded(self) -> bool {
+        self == ToneMapper::Agx
+    }
+
+    // linear in, out in [0, 1] (see display_encoded), color in space. The
+    // fits were made for sRGB primaries, in other spaces they're close enough
+    pub fn apply(self, color: Color, space: ColorSpace) -> Color {
+        match self {
+            ToneMapper::Clip => color,
+            ToneMapper::Reinhard => color / (1.0 + space.luminance(color).max(0.0)),
+            ToneMapper::Aces => {
+                let v = mul(&ACES_INPUT, color);
+                let fit = |v: f64| (v * (v + 0.0245786) - 0.000090537) / (v * (0.983729 * v + 0.4329510) + 0.238081);
+                mul(&ACES_OUTPUT, Color::new(fit(v.x()), fit(v.y()), fit(v.z())))
+            }
+            ToneMapper::Agx => {
+                let v = mul(&AGX_INSET, color);
+                let mut result = Color::new(0.0, 0.0, 0.0);
+                for k in 0..3 {
+                    let log = v[k].max(1e-10).log2().clamp(AGX_MIN_EV, AGX_MAX_EV);
+                    result[k] = agx_contrast((log - AGX_MIN_EV) / (AGX_MAX_EV - AGX_MIN_EV));
+                }
+                mul(&AGX_OUTSET, result)
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct DisplayTransform {
+    pub exposure: f64,      // in stops (EV), +1 is twice as bright
+    pub tone_mapper: ToneMapper,
+    pub dither: bool,
+    pub seed: u64,                  // of the dither noise, the render's seed
+    pub output_space: ColorSpace    // srgb or display-p3
+}
+
+impl Default for DisplayTransform {
+    fn default() -> DisplayTransform {
+        DisplayTransform{ exposure: 0.0, tone_mapper: ToneMapper::Clip, dither: true, seed: 0, output_space: ColorSpace::Srgb }
+    }
+}
+
+impl DisplayTransform {
+    // the 8-bit values of the pixel at (row, col), color in the output
+    // space. The seed and the position pick the dither noise
+    pub fn to_8bit(&self, color: Color, row: u32, col: u32) -> [u32; 3] {
+        let color = self.tone_mapper.apply(color * 2.0_f64.powf(self.exposure), self.output_space);
+        let mut result = [0; 3];
+        for (k, value) in result.iter_mut().enumerate() {
+            let display = color[k].clamp(0.0, 1.0);
+            let encoded = if self.tone_mapper.display_encoded() { display } else { srgb_oetf(display) } * MAX_COLOR;
+            // triangular noise of one step either way, gone after rounding on
+            // average. Black and full white stay exactly that
+            let noise = if self.dither && display > 0.0 && display < 1.0 {
+                let bits = hash(&[self.seed, row as u64, col as u64, k as u64]);
+                (bits >> 40) as f64 / (1_u64 << 24) as f64 + (bits & 0xff_ffff) as f64 / (1_u64 << 24) as f64 - 1.0
+            } else { 0.0 };
+            *value = (encoded + noise).round().clamp(0.0, MAX_COLOR) as u32;
+        }
+        result
+    }
+}
+
+// linear to the sRGB transfer curve, a bit of straight line near black
+// and a 2.4 power above
+pub fn srgb_oetf(x: f64) -> f64 {
+    if x <= 0.0031308 { 12.92 * x } else { 1.055 * x.powf(1.0 / 2.4) - 0.055 }
+}
+
+// linear sRGB into the ACES working space, with the reference rendering's saturation
+const ACES_INPUT: Mat3 = [[0.59719, 0.35458, 0.04823],
+                          [0.07600, 0.90834, 0.01566],
+                          [0.02840, 0.13383, 0.83777]];
+const ACES_OUTPUT: Mat3 = [[ 1.60475, -0.53108, -0.07367],
+                           [-0.10208,  1.10813, -0.00605],
+                           [-0.00327, -0.07276,  1.07602]];
+
+const AGX_MIN_EV: f64 = -12.47393;
+const AGX_MAX_EV: f64 = 4.026069;
+const AGX_INSET: Mat3 = [[0.842479062253094, 0.0784335999999992, 0.0792237451477643],
+                         [0.0423282422610123, 0.878468636469772, 0.0791661274605434],
+                         [0.0423756549057051, 0.0784336, 0.879142973793104]];
+const AGX_OUTSET: Mat3 = [[1.19687900512017, -0.0980208811401368, -0.0990297440797205],
+                          [-0.0528968517574562, 1.15190312990417, -0.0989611768448433],
+                          [-0.0529716355144438, -0.0980434501171241, 1.15107367264116]];
+
+// a polynomial fit of AgX's default contrast sigmoid on [0, 1]
+fn agx_contrast(x: f64) -> f64 {
+    let x2 = x * x;
+    let x4 = x2 * x2;
+    15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x - 0.00232
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grey(value: f64) -> Color {
+        Color::new(value, value, value)
+    }
+
+    #[test]
+    fn black_and_white_are_not_dithered() {
+        let display = DisplayTransform::default();
+        for row in 0..16 {
+            for col in 0..16 {
+                assert_eq!(display.to_8bit(grey(0.0), row, col), [0, 0, 0]);
+                assert_eq!(display.to_8bit(grey(2.0), row, col), [255, 255, 255]);
+            }
+        }
+    }
+
+    #[test]
+    fn the_seed_picks_the_dither_noise() {
+        let image = |seed: u64| {
+            let display = DisplayTransform{ seed, ..DisplayTransform::default() };
+            (0..64).map(|col| display.to_8bit(grey(0.2), 0, col)).collect::<Vec<_>>()
+        };
+        assert_eq!(image(1), image(1));
+        assert_ne!(image(1), image(2));
+    }
+
+    #[test]
+    fn agx_is_not_encoded_twice() {
+        let display = DisplayTransform{ tone_mapper: ToneMapper::Agx, dither: false, ..DisplayTransform::default() };
+        let agx = ToneMapper::Agx.apply(grey(0.18), ColorSpace::Srgb);
+        let expected = (agx[0].clamp(0.0, 1.0) * MAX_COLOR).round() as u32;
+        assert_eq!(display.to_8bit(grey(0.18), 0, 0)[0], expected);
+        // middle grey comes out around the middle of the display range
+        assert!((100..=140).contains(&expected), "{}", expected);
+    }
+}
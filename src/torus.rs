@@ -0,0 +1,123 @@
+// A torus (donut) around center, lying in the xz plane with +y as its axis.
+// Wrap it in a Transformed to orient it differently.
+use crate::vec3::{Vec3, Point3};
+use crate::ray::Ray;
+use crate::hittable::{Hittable, HitRecord};
+use crate::interval::Interval;
+use crate::material::Material;
+use crate::aabb::Aabb;
+use crate::roots::{solve_quadratic, solve_quartic};
+
+use std::sync::Arc;
+use std::f64::consts::PI;
+
+pub struct Torus {
+    center: Point3,
+    major_radius: f64,  // from the center to the middle of the tube
+    minor_radius: f64,  // radius of the tube
+    material: Arc<dyn Material>
+}
+
+impl Torus {
+    pub fn new(center: Point3, major_radius: f64, minor_radius: f64, material: Arc<dyn Material>) -> Torus {
+        Torus{ center, major_radius, minor_radius, material }
+    }
+}
+
+impl Hittable for Torus {
+    fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        // the quartic is badly conditioned for far away origins and long
+        // directions, so solve it with a unit direction starting from where
+        // the ray enters the bounding sphere, then map t back
+        let scale = ray.direction().length();
+        let d = ray.direction() / scale;
+        let o = ray.origin() - self.center;
+        let bound = self.major_radius + self.minor_radius;
+        let entry = solve_quadratic(1.0, 2.0 * o.dot(&d), o.length_squared() - bound * bound);
+        if entry.is_empty() { return None; }
+        let start = entry[0].max(0.0);
+        let o = o + start * d;
+
+        // (|p|^2 + R^2 - r^2)^2 = 4 R^2 (x^2 + z^2), with p = o + s*d and |d| = 1
+        let r2 = self.major_radius.powi(2);
+        let od = o.dot(&d);
+        let k = o.length_squared() + r2 - self.minor_radius.powi(2);
+        let roots = solve_quartic(1.0,
+                                  4.0 * od,
+                                  2.0 * k + 4.0 * od * od - 4.0 * r2 * (d.x().powi(2) + d.z().powi(2)),
+                                  4.0 * od * k - 8.0 * r2 * (o.x()*d.x() + o.z()*d.z()),
+                                  k * k - 4.0 * r2 * (o.x().powi(2) + o.z().powi(2)));
+
+        let t = roots.into_iter()
+                     .map(|s| (s + start) / scale)
+                     .find(|&t| ray_t.surrounds(t))?;
+
+        // the normal points from the nearest point on the tube's center circle
+        let p = ray.at(t) - self.center;
+        let ring = (p.x().powi(2) + p.z().powi(2)).sqrt();
+        let circle = if ring > 0.0 { Vec3::new(p.x(), 0.0, p.z()) * (self.major_radius / ring) }
+                     else { Vec3::new(self.major_radius, 0.0, 0.0) };
+        let normal = (p - circle).unit_vector();
+
+        // u goes around the y axis, v goes around the tube
+        let uv = ((p.z().atan2(p.x()) + PI) / (2.0*PI),
+                  (p.y().atan2(ring - self.major_radius) + PI) / (2.0*PI));
+        Some(HitRecord::new(ray, t, normal, uv, self.material.clone()))
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let outer = self.major_radius + self.minor_radius;
+        let extent = Vec3::new(outer, self.minor_radius, outer);
+        Aabb::new(self.center - extent, self.center + extent)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec3::Color;
+    use crate::material::Lambertian;
+
+    // R = 2, r = 0.5 around the origin, so the hole spans |x| < 1.5
+    fn donut() -> Torus {
+        Torus::new(Point3::new(0.0, 0.0, 0.0), 2.0, 0.5, Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))))
+    }
+    fn shoot(origin: Point3, direction: Vec3) -> Option<HitRecord> {
+        donut().hit(&Ray::new(origin, direction), Interval::new(0.001, f64::INFINITY))
+    }
+
+    #[test]
+    fn rays_down_the_hole_miss() {
+        assert!(shoot(Point3::new(0.0, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0)).is_none());
+        assert!(shoot(Point3::new(1.4, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0)).is_none());
+        assert!(shoot(Point3::new(1.6, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0)).is_some());
+    }
+
+    #[test]
+    fn rays_across_the_hole_hit_the_inner_wall() {
+        // from inside the hole the first hit is the inner side of the tube
+        let record = shoot(Point3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0)).expect("inner wall missed");
+        assert!((record.t - 1.5).abs() < 1e-9, "t = {}", record.t);
+        assert!((record.normal - Vec3::new(-1.0, 0.0, 0.0)).length() < 1e-9);
+
+        // from outside, through the tube, across the hole and out the other side
+        let record = shoot(Point3::new(-10.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0)).expect("outer wall missed");
+        assert!((record.t - 7.5).abs() < 1e-9, "t = {}", record.t);
+        let ray = Ray::new(Point3::new(-10.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        let exits: Vec<f64> = [8.5, 11.5, 12.5].iter().map(|&after| {
+            donut().hit(&ray, Interval::new(after - 0.5, f64::INFINITY)).expect("missed a wall").t
+        }).collect();
+        for (t, expected) in exits.iter().zip([8.5, 11.5, 12.5]) {
+            assert!((t - expected).abs() < 1e-9, "t = {}", t);
+        }
+    }
+
+    #[test]
+    fn grazing_the_top_of_the_tube() {
+        // tangent to the top of the tube at y = r, from far away
+        let just_under = shoot(Point3::new(-1e4, 0.5 - 1e-6, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        assert!(just_under.is_some());
+        let just_over = shoot(Point3::new(-1e4, 0.5 + 1e-6, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        assert!(just_over.is_none());
+    }
+}
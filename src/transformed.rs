@@ -0,0 +1,122 @@
+// A hittable wrapper placing any object into the world with an affine transform.
+// The same Arc<dyn Hittable> can be wrapped many times (instancing),
+// each with its own object-to-world matrix.
+// The transform may also be animated, moving from one transform at time 0
+// to another at time 1, which gives motion blur.
+use crate::ray::Ray;
+use crate::hittable::{Hittable, HitRecord, Span};
+use crate::interval::Interval;
+use crate::mat4::{Mat4, Trs};
+use crate::aabb::Aabb;
+
+use std::sync::Arc;
+
+// how many instants the bounding box of an animated transform is built from
+const MOTION_BOX_STEPS: u32 = 32;
+
+pub struct Transformed {
+    object: Arc<dyn Hittable>,
+    object_to_world: Mat4,
+    world_to_object: Mat4,
+    normal_to_world: Mat4,  // inverse transpose, keeps normals perpendicular under non-uniform scaling
+    motion: Option<(Trs, Trs)>  // transforms at time 0 and 1, None for a still object
+}
+
+// the three matrices at one instant
+struct Matrices {
+    object_to_world: Mat4,
+    world_to_object: Mat4,
+    normal_to_world: Mat4
+}
+
+impl Transformed {
+    // None if the transform can't be undone, e.g. it scales by 0
+    pub fn try_new(object: Arc<dyn Hittable>, object_to_world: Mat4) -> Option<Transformed> {
+        let world_to_object = object_to_world.inverse()?;
+        Some(Transformed{ object, object_to_world, world_to_object,
+                          normal_to_world: world_to_object.transpose(),
+                          motion: None })
+    }
+    // panics if the transform can't be undone, see try_new
+    pub fn new(object: Arc<dyn Hittable>, object_to_world: Mat4) -> Transformed {
+        Transformed::try_new(object, object_to_world).expect("transform matrix is not invertible!")
+    }
+
+    // moves from start at time 0 to end at time 1, interpolating translation,
+    // rotation and scale separately so that rotations stay rigid. None if
+    // either end can't be undone, or a scale changes sign on the way and so
+    // passes through 0
+    pub fn try_moving(object: Arc<dyn Hittable>, start: Mat4, end: Mat4) -> Option<Transformed> {
+        let (from, to) = (start.to_trs(), end.to_trs());
+        if !(0..3).all(|k| from.scale[k] * to.scale[k] > 0.0) { return None; }
+        let mut transformed = Transformed::try_new(object, start)?;
+        transformed.motion = Some((from, to));
+        Some(transformed)
+    }
+    // panics where try_moving gives None
+    pub fn moving(object: Arc<dyn Hittable>, start: Mat4, end: Mat4) -> Transformed {
+        Transformed::try_moving(object, start, end).expect("transform is not invertible during the motion!")
+    }
+
+    fn matrices(&self, time: f64) -> Matrices {
+        match &self.motion {
+            None => Matrices{ object_to_world: self.object_to_world,
+                              world_to_object: self.world_to_object,
+                              normal_to_world: self.normal_to_world },
+            Some((start, end)) => {
+                let trs = Trs::lerp(start, end, time);
+                let world_to_object = trs.inverse_matrix();
+                Matrices{ object_to_world: trs.to_matrix(), world_to_object,
+                          normal_to_world: world_to_object.transpose() }
+            }
+        }
+    }
+
+    // direction is not normalized after transforming, so t stays the same
+    // in both spaces and intervals can be passed through untouched
+    fn to_object(matrices: &Matrices, ray: &Ray) -> Ray {
+        Ray::with_time(matrices.world_to_object.transform_point(ray.origin()),
+                       matrices.world_to_object.transform_vector(ray.direction()),
+                       ray.time()).with_wavelength(ray.wavelength())
+    }
+    fn to_world(matrices: &Matrices, mut record: HitRecord) -> HitRecord {
+        record.p = matrices.object_to_world.transform_point(record.p);
+        record.normal = matrices.normal_to_world.transform_vector(record.normal).unit_vector();
+        record
+    }
+}
+
+impl Hittable for Transformed {
+    fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        let matrices = self.matrices(ray.time());
+        let record = self.object.hit(&Self::to_object(&matrices, ray), ray_t)?;
+        Some(Self::to_world(&matrices, record))
+    }
+
+    fn spans(&self, ray: &Ray) -> Vec<Span> {
+        let matrices = self.matrices(ray.time());
+        self.object.spans(&Self::to_object(&matrices, ray)).into_iter()
+            .map(|span| Span{ enter: Self::to_world(&matrices, span.enter),
+                              exit: Self::to_world(&matrices, span.exit) })
+            .collect()
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let local = self.object.bounding_box();
+        match &self.motion {
+            None => local.transform(&self.object_to_world),
+            // sweep the box through the motion, padded for the little arcs
+            // a rotating corner makes between two steps
+            Some(_) => {
+                let mut bbox = Aabb::empty();
+                for step in 0..=MOTION_BOX_STEPS {
+                    let time = step as f64 / MOTION_BOX_STEPS as f64;
+                    bbox = bbox.union(&local.transform(&self.matrices(time).object_to_world));
+                }
+                if !bbox.is_finite() { return bbox; }
+                let size = bbox.max() - bbox.min();
+                bbox.pad(0.01 * size.x().max(size.y()).max(size.z()))
+            }
+        }
+    }
+}
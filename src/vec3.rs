@@ -11,7 +11,7 @@ pub struct Vec3 {
 }
 
 impl Vec3 {
-    pub fn new(x: f64, y: f64, z: f64) -> Vec3 {
+    pub const fn new(x: f64, y: f64, z: f64) -> Vec3 {
         Vec3{ e: [x, y, z] } 
     }
 
@@ -51,23 +51,30 @@ impl Vec3 {
         }
     }
     
-    // generate a random vector
-    pub fn random(min: f64, max: f64) -> Vec3 {
-        let rng = thread_rng;
-        Vec3::new(rng().gen_range(min..max),
-                  rng().gen_range(min..max), 
-                  rng().gen_range(min..max))
+    // generate a random vector, pass in a seeded rng to get the same ones every run
+    pub fn random(rng: &mut impl Rng, min: f64, max: f64) -> Vec3 {
+        Vec3::new(rng.gen_range(min..max),
+                  rng.gen_range(min..max), 
+                  rng.gen_range(min..max))
     }
     // generate a unit vector
-    pub fn random_in_unit_sphere() -> Vec3 {
+    pub fn random_in_unit_sphere(rng: &mut impl Rng) -> Vec3 {
         loop {
-            let p = Vec3::random(-1.0, 1.0);
+            let p = Vec3::random(rng, -1.0, 1.0);
             match p.length_squared() > 1.0 {
                 true => { continue; }
                 false => { break p.unit_vector(); }
             }
         }
     }
+    // a uniformly distributed unit vector from a sample in [0, 1)^2,
+    // the same distribution as random_in_unit_sphere
+    pub fn sample_unit_sphere((u1, u2): (f64, f64)) -> Vec3 {
+        let z = 1.0 - 2.0 * u1;
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        let phi = 2.0 * std::f64::consts::PI * u2;
+        Vec3::new(r * phi.cos(), r * phi.sin(), z)
+    }
     // check whether three directions of a vector all near zero
     // this is used to determine whether sum of two vectors 
     // comes from two reverse vectors
@@ -84,9 +91,18 @@ impl Vec3 {
         
         vertical + parallel
     }
-    pub fn random_in_unit_disk() -> Vec3 {
+    // build two unit vectors perpendicular to self (and to each other),
+    // used to lay a 2D coordinate system onto a plane with this normal
+    pub fn orthonormal_basis(&self) -> (Vec3, Vec3) {
+        let n = self.unit_vector();
+        let helper = if n.x().abs() > 0.9 { Vec3::new(0.0, 1.0, 0.0) } else { Vec3::new(1.0, 0.0, 0.0) };
+        let a = n.cross(&helper).unit_vector();
+        let b = n.cross(&a);
+        (a, b)
+    }
+    pub fn random_in_unit_disk(rng: &mut impl Rng) -> Vec3 {
         loop {
-            let mut p = Vec3::random(-1.0, 1.0);
+            let mut p = Vec3::random(rng, -1.0, 1.0);
             p.e[2] = 0.0; 
 
             match p.length_squared() < 1.0 {